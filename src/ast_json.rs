@@ -0,0 +1,160 @@
+use crate::semantic_analyzer::Type;
+use crate::syntax_analyzer::{Expression, Function, SpannedStatement, Statement};
+
+//
+// ===== AST AS JSON =====
+//
+// Writes the parsed tree out as JSON for tools outside the compiler. Every
+// statement and expression is an object with a "kind" naming its variant and
+// one field per field of the variant, types are strings like "Int" or "[Int]".
+//
+
+impl Function {
+    pub fn to_json(&self) -> String {
+        let params: Vec<String> = self
+            .params
+            .iter()
+            .map(|(name, ty)| format!("{{\"name\":{},\"type\":{}}}", json_string(name), type_json(ty)))
+            .collect();
+        let return_type = self.return_type.as_ref().map_or("null".to_string(), type_json);
+        format!(
+            "{{\"name\":{},\"params\":[{}],\"return_type\":{},\"body\":{}}}",
+            json_string(&self.name),
+            params.join(","),
+            return_type,
+            statements_json(&self.body)
+        )
+    }
+}
+
+impl Statement {
+    pub fn to_json(&self) -> String {
+        match self {
+            Statement::VarDecl { name, value, mutable } => format!(
+                "{{\"kind\":\"VarDecl\",\"name\":{},\"mutable\":{},\"value\":{}}}",
+                json_string(name),
+                mutable,
+                value.to_json()
+            ),
+            Statement::Assign { name, value } => {
+                format!("{{\"kind\":\"Assign\",\"name\":{},\"value\":{}}}", json_string(name), value.to_json())
+            }
+            Statement::Expr(expr) => format!("{{\"kind\":\"Expr\",\"expr\":{}}}", expr.to_json()),
+            Statement::Return(value) => {
+                let value = value.as_ref().map_or("null".to_string(), Expression::to_json);
+                format!("{{\"kind\":\"Return\",\"value\":{}}}", value)
+            }
+            Statement::If { cond, then_body, else_body } => format!(
+                "{{\"kind\":\"If\",\"cond\":{},\"then_body\":{},\"else_body\":{}}}",
+                cond.to_json(),
+                statements_json(then_body),
+                else_body.as_deref().map_or("null".to_string(), statements_json)
+            ),
+            Statement::While { cond, body } => {
+                format!("{{\"kind\":\"While\",\"cond\":{},\"body\":{}}}", cond.to_json(), statements_json(body))
+            }
+            Statement::Break => "{\"kind\":\"Break\"}".to_string(),
+            Statement::Continue => "{\"kind\":\"Continue\"}".to_string(),
+            Statement::Block(body) => format!("{{\"kind\":\"Block\",\"body\":{}}}", statements_json(body)),
+        }
+    }
+}
+
+impl Expression {
+    pub fn to_json(&self) -> String {
+        match self {
+            Expression::Integer(n) => format!("{{\"kind\":\"Integer\",\"value\":{}}}", n),
+            //JSON has no inf or NaN, they come out as null
+            Expression::Float(n) if !n.is_finite() => "{\"kind\":\"Float\",\"value\":null}".to_string(),
+            Expression::Float(n) => format!("{{\"kind\":\"Float\",\"value\":{:?}}}", n),
+            Expression::Boolean(b) => format!("{{\"kind\":\"Boolean\",\"value\":{}}}", b),
+            Expression::String(s) => format!("{{\"kind\":\"String\",\"value\":{}}}", json_string(s)),
+            Expression::Char(c) => format!("{{\"kind\":\"Char\",\"value\":{}}}", json_string(&c.to_string())),
+            Expression::Ident(name) => format!("{{\"kind\":\"Ident\",\"name\":{}}}", json_string(name)),
+            Expression::BinaryOp { left, op, right } => format!(
+                "{{\"kind\":\"BinaryOp\",\"op\":{},\"left\":{},\"right\":{}}}",
+                json_string(op),
+                left.to_json(),
+                right.to_json()
+            ),
+            Expression::Call { name, args } => {
+                format!("{{\"kind\":\"Call\",\"name\":{},\"args\":{}}}", json_string(name), expressions_json(args))
+            }
+            Expression::Unary { op, operand } => {
+                format!("{{\"kind\":\"Unary\",\"op\":{},\"operand\":{}}}", json_string(op), operand.to_json())
+            }
+            Expression::Array(elements) => format!("{{\"kind\":\"Array\",\"elements\":{}}}", expressions_json(elements)),
+            Expression::Index { base, index } => {
+                format!("{{\"kind\":\"Index\",\"base\":{},\"index\":{}}}", base.to_json(), index.to_json())
+            }
+        }
+    }
+}
+
+// a whole program, one object per function
+pub fn functions_to_json(funcs: &[Function]) -> String {
+    format!("[{}]", funcs.iter().map(Function::to_json).collect::<Vec<_>>().join(","))
+}
+
+fn statements_json(stmts: &[SpannedStatement]) -> String {
+    format!("[{}]", stmts.iter().map(|s| s.stmt.to_json()).collect::<Vec<_>>().join(","))
+}
+
+fn expressions_json(exprs: &[Expression]) -> String {
+    format!("[{}]", exprs.iter().map(Expression::to_json).collect::<Vec<_>>().join(","))
+}
+
+// the name the type is written with in source, as a JSON string
+fn type_json(ty: &Type) -> String {
+    fn name(ty: &Type) -> String {
+        match ty {
+            Type::Array(elem) => format!("[{}]", name(elem)),
+            other => format!("{:?}", other),
+        }
+    }
+    json_string(&name(ty))
+}
+
+// quotes a string, escaping what JSON doesn't allow raw
+fn json_string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex_layer::tokenize;
+
+    #[test]
+    fn var_decl_and_return_shape() {
+        let tokens = tokenize("func main() -> Int { var x = 5; return x; }").unwrap();
+        let funcs = crate::parse(&tokens).unwrap();
+        assert_eq!(
+            funcs[0].to_json(),
+            concat!(
+                r#"{"name":"main","params":[],"return_type":"Int","body":["#,
+                r#"{"kind":"VarDecl","name":"x","mutable":true,"value":{"kind":"Integer","value":5}},"#,
+                r#"{"kind":"Return","value":{"kind":"Ident","name":"x"}}]}"#,
+            )
+        );
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        let expr = Expression::String("say \"hi\"\n".to_string());
+        assert_eq!(expr.to_json(), r#"{"kind":"String","value":"say \"hi\"\n"}"#);
+    }
+}
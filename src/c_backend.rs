@@ -0,0 +1,155 @@
+use std::collections::HashSet;
+
+use crate::intermediate_code_generator::{IRFunction, IRInstr, IRValue};
+use crate::semantic_analyzer::Type;
+
+//
+// ===== C BACKEND =====
+//
+// Renders three-address IR as C so a program can be built with a real C
+// compiler. Only integer programs are supported: every var and temp is a
+// `long`, bools become 0/1 and print becomes printf. Floats, strings, chars and arrays
+// come out as a comment saying they aren't supported.
+//
+
+// A C function body, one statement per IR instruction. Names are declared
+// where they're first assigned.
+pub fn emit_c(ir: &[IRInstr]) -> String {
+    emit_body(ir, HashSet::new(), "return;")
+}
+
+// A whole C function definition: `long add(long a, long b) { ... }`
+pub fn emit_c_function(func: &IRFunction) -> String {
+    let params: Vec<String> = func.params.iter().map(|(name, _)| format!("long {}", c_name(name))).collect();
+    let declared = func.params.iter().map(|(name, _)| name.clone()).collect();
+    let bare_return = if func.name == "main" { "return 0;" } else { "return;" };
+    format!("{} {{\n{}}}\n", signature(func, &params), emit_body(&func.code, declared, bare_return))
+}
+
+// Prototypes for every function, then every definition, so calls can go in any order
+pub fn emit_c_program(funcs: &[IRFunction]) -> String {
+    let mut out = String::from("#include <stdio.h>\n\n");
+    for func in funcs {
+        let params: Vec<String> = func.params.iter().map(|_| "long".to_string()).collect();
+        out.push_str(&format!("{};\n", signature(func, &params)));
+    }
+    for func in funcs {
+        out.push('\n');
+        out.push_str(&emit_c_function(func));
+    }
+    out
+}
+
+// main has to return int in C, functions without a return type are void
+fn signature(func: &IRFunction, params: &[String]) -> String {
+    let return_type = match (func.name.as_str(), &func.return_type) {
+        ("main", _) => "int",
+        (_, Type::Unit) => "void",
+        _ => "long",
+    };
+    let params = if params.is_empty() { "void".to_string() } else { params.join(", ") };
+    format!("{} {}({})", return_type, c_name(&func.name), params)
+}
+
+// bare_return is what a return with no value becomes, main still has to give back an int
+fn emit_body(ir: &[IRInstr], mut declared: HashSet<String>, bare_return: &str) -> String {
+    let read = read_names(ir);
+    let mut out = String::new();
+    for instr in ir {
+        // `long x = ...` the first time x is written, `x = ...` after that
+        let mut assign = |target: &str, value: String| {
+            let decl = if declared.insert(target.to_string()) { "long " } else { "" };
+            format!("{}{} = {};", decl, c_name(target), value)
+        };
+        let line = match instr {
+            IRInstr::Assign(target, value) => match c_value(value) {
+                Some(value) => assign(target, value),
+                None => unsupported(instr),
+            },
+            IRInstr::BinaryOp(res, l, op, r) => assign(res, format!("{} {} {}", c_name(l), op, c_name(r))),
+            IRInstr::UnaryOp(res, op, operand) => assign(res, format!("{}{}", op, c_name(operand))),
+            IRInstr::Call(_, name, args) if name == "print" => {
+                format!("printf(\"%ld\\n\", {});", args.iter().map(|a| c_name(a)).collect::<Vec<_>>().join(", "))
+            }
+            IRInstr::Call(res, name, args) => {
+                let call = format!("{}({})", c_name(name), args.iter().map(|a| c_name(a)).collect::<Vec<_>>().join(", "));
+                // a void function's result is never read, and it can't be assigned in C either
+                if read.contains(res.as_str()) { assign(res, call) } else { format!("{};", call) }
+            }
+            IRInstr::Return(Some(name)) => format!("return {};", c_name(name)),
+            IRInstr::Return(None) => bare_return.to_string(),
+            // the empty statement keeps a label legal right before a declaration or the closing brace
+            IRInstr::Label(label) => format!("{}:;", label),
+            IRInstr::Jump(label) => format!("goto {};", label),
+            IRInstr::JumpIfFalse(cond, label) => format!("if (!{}) goto {};", c_name(cond), label),
+            IRInstr::MakeArray(..) | IRInstr::Index(..) | IRInstr::Phi(..) => unsupported(instr),
+        };
+        if !matches!(instr, IRInstr::Label(_)) {
+            out.push_str("    ");
+        }
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+// every name some instruction reads
+fn read_names(ir: &[IRInstr]) -> HashSet<&str> {
+    ir.iter().flat_map(|instr| instr.defs_and_uses().1).map(String::as_str).collect()
+}
+
+fn c_value(value: &IRValue) -> Option<String> {
+    match value {
+        IRValue::Int(n) => Some(n.to_string()),
+        IRValue::Bool(b) => Some(if *b { "1" } else { "0" }.to_string()),
+        IRValue::Var(name) | IRValue::Temp(name) => Some(c_name(name)),
+        IRValue::Float(_) | IRValue::Str(_) | IRValue::Char(_) => None,
+    }
+}
+
+// shadowing vars are named like x.1 and temps like %1 in the IR, neither is
+// a C identifier, so both get a __ the way compiler-made names do
+fn c_name(name: &str) -> String {
+    match name.strip_prefix('%') {
+        Some(n) => format!("__t{}", n),
+        None => name.replace('.', "__"),
+    }
+}
+
+fn unsupported(instr: &IRInstr) -> String {
+    format!("/* unsupported: {} */", instr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // main's IR straight out of the generator
+    fn main_ir(src: &str) -> Vec<IRInstr> {
+        let tokens = crate::lex_layer::tokenize(src).unwrap();
+        let funcs = crate::parse(&tokens).unwrap();
+        crate::generate_ir(&funcs).into_iter().find(|f| f.name == "main").unwrap().code
+    }
+
+    #[test]
+    fn integer_body_matches_golden() {
+        let code = main_ir("func main() -> Int { var x = 1; x = x + 2; x = x * 3; return x; }");
+        let expected = "    long x = 1;\n    long __t2 = 2;\n    long __t1 = x + __t2;\n    x = __t1;\n    \
+                        long __t4 = 3;\n    long __t3 = x * __t4;\n    x = __t3;\n    return x;\n";
+        assert_eq!(emit_c(&code), expected);
+    }
+
+    #[test]
+    fn params_are_declared_in_the_signature() {
+        let func = IRFunction {
+            name: "add".to_string(),
+            params: vec![("a".to_string(), Type::Int), ("b".to_string(), Type::Int)],
+            return_type: Type::Int,
+            code: vec![
+                IRInstr::BinaryOp("%1".to_string(), "a".to_string(), "+".to_string(), "b".to_string()),
+                IRInstr::Return(Some("%1".to_string())),
+            ],
+        };
+        assert_eq!(emit_c_function(&func), "long add(long a, long b) {\n    long __t1 = a + b;\n    return __t1;\n}\n");
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+use crate::intermediate_code_generator::IRInstr;
+
+//
+// ===== CONTROL-FLOW GRAPH =====
+//
+// Splits a function's IR into basic blocks: runs of instructions that are
+// always executed start to finish. A block starts at the first instruction, at
+// every Label, and right after every Return/Jump/JumpIfFalse.
+//
+
+// Instructions code[start..end] of the function the graph was built from
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub end: usize,
+}
+
+// Block 0 is the entry. An edge (a, b) means control can go from the end of
+// block a to the start of block b.
+#[derive(Debug, Clone)]
+pub struct Cfg {
+    pub blocks: Vec<BasicBlock>,
+    pub edges: Vec<(usize, usize)>,
+}
+
+impl Cfg {
+    //blocks control can go to straight after this one
+    pub fn successors(&self, block: usize) -> impl Iterator<Item = usize> + '_ {
+        self.edges.iter().filter(move |(from, _)| *from == block).map(|(_, to)| *to)
+    }
+
+    //blocks that can go straight to this one
+    pub fn predecessors(&self, block: usize) -> impl Iterator<Item = usize> + '_ {
+        self.edges.iter().filter(move |(_, to)| *to == block).map(|(from, _)| *from)
+    }
+}
+
+pub fn build_cfg(code: &[IRInstr]) -> Cfg {
+    // find where every block starts
+    let mut starts = Vec::new();
+    for (i, instr) in code.iter().enumerate() {
+        let after_branch = i > 0
+            && matches!(code[i - 1], IRInstr::Return(_) | IRInstr::Jump(_) | IRInstr::JumpIfFalse(_, _));
+        if i == 0 || after_branch || matches!(instr, IRInstr::Label(_)) {
+            starts.push(i);
+        }
+    }
+
+    let blocks: Vec<BasicBlock> = starts
+        .iter()
+        .enumerate()
+        .map(|(b, &start)| BasicBlock { start, end: starts.get(b + 1).copied().unwrap_or(code.len()) })
+        .collect();
+
+    // label name -> block it starts
+    let label_blocks: HashMap<&str, usize> = blocks
+        .iter()
+        .enumerate()
+        .filter_map(|(b, block)| match &code[block.start] {
+            IRInstr::Label(label) => Some((label.as_str(), b)),
+            _ => None,
+        })
+        .collect();
+
+    let mut edges = Vec::new();
+    for (b, block) in blocks.iter().enumerate() {
+        let fallthrough = (b + 1 < blocks.len()).then_some(b + 1);
+        match &code[block.end - 1] {
+            IRInstr::Return(_) => {}
+            IRInstr::Jump(label) => {
+                edges.extend(label_blocks.get(label.as_str()).map(|&to| (b, to)));
+            }
+            IRInstr::JumpIfFalse(_, label) => {
+                edges.extend(fallthrough.map(|to| (b, to)));
+                edges.extend(label_blocks.get(label.as_str()).map(|&to| (b, to)));
+            }
+            _ => edges.extend(fallthrough.map(|to| (b, to))),
+        }
+    }
+
+    Cfg { blocks, edges }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intermediate_code_generator::IRValue;
+
+    // main's IR straight out of the generator
+    fn main_ir(src: &str) -> Vec<IRInstr> {
+        let tokens = crate::lex_layer::tokenize(src).unwrap();
+        let funcs = crate::parse(&tokens).unwrap();
+        crate::generate_ir(&funcs).into_iter().find(|f| f.name == "main").unwrap().code
+    }
+
+    #[test]
+    fn if_else_makes_a_diamond() {
+        let code = main_ir("func main() -> Int { var x = 1; if (x == 2) { var y = 3; } else { var y = 4; } return x; }");
+        let cfg = build_cfg(&code);
+        // condition, then branch, else branch, join
+        assert_eq!(cfg.blocks.len(), 4);
+        assert_eq!(cfg.edges, vec![(0, 1), (0, 2), (1, 3), (2, 3)]);
+        assert!(matches!(code[cfg.blocks[2].start], IRInstr::Label(_)));
+        assert!(matches!(code[cfg.blocks[3].end - 1], IRInstr::Return(_)));
+    }
+
+    #[test]
+    fn block_after_a_jump_has_no_fallthrough_edge() {
+        let code = vec![
+            IRInstr::Jump("L1".to_string()),
+            IRInstr::Assign("x".to_string(), IRValue::Int(1)),
+            IRInstr::Label("L1".to_string()),
+        ];
+        let cfg = build_cfg(&code);
+        assert_eq!(cfg.blocks.len(), 3);
+        assert_eq!(cfg.edges, vec![(0, 2), (1, 2)]);
+    }
+}
@@ -0,0 +1,169 @@
+use crate::syntax_analyzer::Expression;
+use crate::target_code_generator::VMValue;
+
+//
+// ===== CONSTANT EVALUATION =====
+//
+// Works out the value of an expression made only of literals, like 2 * (3 + 4),
+// straight from the AST. Anything that reads a variable, calls a function or
+// would fail or overflow at runtime gives None and is left for the VM.
+//
+
+pub fn eval_const(expr: &Expression) -> Option<VMValue> {
+    match expr {
+        Expression::Integer(n) => Some(VMValue::Int(*n)),
+        Expression::Float(n) => Some(VMValue::Float(*n)),
+        Expression::Boolean(b) => Some(VMValue::Bool(*b)),
+        Expression::String(s) => Some(VMValue::Str(s.clone())),
+        Expression::Char(c) => Some(VMValue::Char(*c)),
+        Expression::Ident(_) | Expression::Call { .. } => None,
+
+        Expression::Unary { op, operand } => eval_unary(op, eval_const(operand)?),
+
+        Expression::BinaryOp { left, op, right } => {
+            let left = eval_const(left)?;
+            let right = eval_const(right)?;
+            eval_binary(op, left, right)
+        }
+
+        Expression::Array(elements) => elements.iter().map(eval_const).collect::<Option<_>>().map(VMValue::Array),
+
+        Expression::Index { base, index } => match (eval_const(base)?, eval_const(index)?) {
+            (VMValue::Array(elements), VMValue::Int(i)) => elements.into_iter().nth(usize::try_from(i).ok()?),
+            _ => None,
+        },
+    }
+}
+
+// `op value` the way the VM works it out, None where it doesn't apply or -i64::MIN overflows
+pub fn eval_unary(op: &str, value: VMValue) -> Option<VMValue> {
+    match (op, value) {
+        ("-", VMValue::Int(n)) => n.checked_neg().map(VMValue::Int),
+        ("-", VMValue::Float(n)) => Some(VMValue::Float(-n)),
+        ("!", VMValue::Bool(b)) => Some(VMValue::Bool(!b)),
+        _ => None,
+    }
+}
+
+// same results the VM would give, but None where the VM would fail or wrap.
+// The optimizer folds IR constants with this too, so there is only one folder
+pub fn eval_binary(op: &str, left: VMValue, right: VMValue) -> Option<VMValue> {
+    match (left, right) {
+        //an Int next to a Float is promoted first
+        (VMValue::Int(a), VMValue::Float(b)) => eval_binary(op, VMValue::Float(a as f64), VMValue::Float(b)),
+        (VMValue::Float(a), VMValue::Int(b)) => eval_binary(op, VMValue::Float(a), VMValue::Float(b as f64)),
+        (VMValue::Float(a), VMValue::Float(b)) => match op {
+            "+" => Some(VMValue::Float(a + b)),
+            "-" => Some(VMValue::Float(a - b)),
+            "*" => Some(VMValue::Float(a * b)),
+            "/" => Some(VMValue::Float(a / b)),
+            "<" => Some(VMValue::Bool(a < b)),
+            ">" => Some(VMValue::Bool(a > b)),
+            "<=" => Some(VMValue::Bool(a <= b)),
+            ">=" => Some(VMValue::Bool(a >= b)),
+            "==" => Some(VMValue::Bool(a == b)),
+            "!=" => Some(VMValue::Bool(a != b)),
+            _ => None,
+        },
+        (VMValue::Int(a), VMValue::Int(b)) => match op {
+            "+" => a.checked_add(b).map(VMValue::Int),
+            "-" => a.checked_sub(b).map(VMValue::Int),
+            "*" => a.checked_mul(b).map(VMValue::Int),
+            "/" => a.checked_div(b).map(VMValue::Int),
+            "%" => (b != 0).then(|| VMValue::Int(a.wrapping_rem(b))),
+            "<" => Some(VMValue::Bool(a < b)),
+            ">" => Some(VMValue::Bool(a > b)),
+            "<=" => Some(VMValue::Bool(a <= b)),
+            ">=" => Some(VMValue::Bool(a >= b)),
+            "==" => Some(VMValue::Bool(a == b)),
+            "!=" => Some(VMValue::Bool(a != b)),
+            _ => None,
+        },
+        (VMValue::Bool(a), VMValue::Bool(b)) => match op {
+            "&&" => Some(VMValue::Bool(a && b)),
+            "||" => Some(VMValue::Bool(a || b)),
+            "==" => Some(VMValue::Bool(a == b)),
+            "!=" => Some(VMValue::Bool(a != b)),
+            _ => None,
+        },
+        (VMValue::Str(a), VMValue::Str(b)) => match op {
+            "+" => Some(VMValue::Str(a + &b)),
+            "<" => Some(VMValue::Bool(a < b)),
+            ">" => Some(VMValue::Bool(a > b)),
+            "<=" => Some(VMValue::Bool(a <= b)),
+            ">=" => Some(VMValue::Bool(a >= b)),
+            "==" => Some(VMValue::Bool(a == b)),
+            "!=" => Some(VMValue::Bool(a != b)),
+            _ => None,
+        },
+        (VMValue::Str(a), VMValue::Int(b)) if op == "*" => repeat(&a, b).map(VMValue::Str),
+        (a, b) => match op {
+            "==" => Some(VMValue::Bool(a == b)),
+            "!=" => Some(VMValue::Bool(a != b)),
+            _ => None,
+        },
+    }
+}
+
+// Longest string a repeat may build, so "ab" * 4000000000000 is an error
+// instead of the process dying on the allocation
+const MAX_REPEAT_BYTES: usize = 1 << 24;
+
+// s written count times in a row, None for a negative count or a result
+// longer than MAX_REPEAT_BYTES, which the VM's Repeat rejects
+pub fn repeat(s: &str, count: i64) -> Option<String> {
+    let times = usize::try_from(count).ok()?;
+    if s.len().checked_mul(times)? > MAX_REPEAT_BYTES {
+        return None;
+    }
+    Some(s.repeat(times))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeat_rejects_negative_and_oversized_counts() {
+        assert_eq!(repeat("ab", 3), Some("ababab".to_string()));
+        assert_eq!(repeat("ab", -2), None);
+        assert_eq!(repeat("ab", 4_000_000_000_000), None);
+    }
+
+    #[test]
+    fn huge_string_repeat_is_left_unfolded() {
+        let expr = Expression::BinaryOp {
+            left: Box::new(Expression::String("ab".to_string())),
+            op: "*".to_string(),
+            right: Box::new(Expression::Integer(4_000_000_000_000)),
+        };
+        assert_eq!(eval_const(&expr), None);
+    }
+
+    fn int(n: i64) -> Box<Expression> {
+        Box::new(Expression::Integer(n))
+    }
+
+    fn binary(left: Box<Expression>, op: &str, right: Box<Expression>) -> Box<Expression> {
+        Box::new(Expression::BinaryOp { left, op: op.to_string(), right })
+    }
+
+    #[test]
+    fn folds_nested_arithmetic() {
+        let expr = binary(int(2), "*", binary(int(3), "+", int(4)));
+        assert_eq!(eval_const(&expr), Some(VMValue::Int(14)));
+    }
+
+    #[test]
+    fn variables_are_not_constant() {
+        let expr = binary(Box::new(Expression::Ident("x".to_string())), "+", int(1));
+        assert_eq!(eval_const(&expr), None);
+    }
+
+    #[test]
+    fn runtime_failures_are_not_folded() {
+        assert_eq!(eval_const(&binary(int(1), "/", binary(int(2), "-", int(2)))), None);
+        assert_eq!(eval_const(&binary(int(i64::MAX), "+", int(1))), None);
+        assert_eq!(eval_const(&binary(int(-7), "%", int(3))), Some(VMValue::Int(-1)));
+    }
+}
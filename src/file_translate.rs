@@ -1,5 +1,11 @@
 use std::fs;
 use std::io;
+use std::io::Read;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+//how long wait_for_change sleeps between looks at the file
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
 
 //get file contents gets what is inside and read_file translates it so tokenize can use it
 fn get_file_contents<'a, E>(file_path: &str, buffer: &'a mut String) -> Result<&'a str, E>
@@ -11,7 +17,55 @@ where
 }
 
 //read_file just reads the file and puts it in a way so that tokenize can use the result
-pub fn read_file(buffer: &mut String) -> Result<&str, std::io::Error> {
-    get_file_contents("myfile.txt", buffer)
+pub fn read_file<'a>(path: &str, buffer: &'a mut String) -> Result<&'a str, std::io::Error> {
+    get_file_contents(path, buffer)
+}
+
+//read_stdin takes the whole program from standard input instead of a file
+pub fn read_stdin(buffer: &mut String) -> io::Result<&str> {
+    read_all(io::stdin().lock(), buffer)
 }
 
+//reads everything the reader has into the buffer
+fn read_all<R: Read>(mut reader: R, buffer: &mut String) -> io::Result<&str> {
+    buffer.clear();
+    reader.read_to_string(buffer)?;
+    Ok(buffer.as_str())
+}
+
+//when the file was last written, None if it can't be read right now
+//(editors sometimes delete and recreate a file when saving)
+pub fn modified_time(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+//blocks until the file's modification time differs from last, then returns the new one
+pub fn wait_for_change(path: &str, last: Option<SystemTime>) -> Option<SystemTime> {
+    loop {
+        thread::sleep(POLL_INTERVAL);
+        let now = modified_time(path);
+        if now.is_some() && now != last {
+            return now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn reads_everything_from_a_reader() {
+        let mut buffer = String::from("left over");
+        let source = read_all(Cursor::new("func main() {\n    return 1;\n}\n"), &mut buffer).unwrap();
+        assert_eq!(source, "func main() {\n    return 1;\n}\n");
+    }
+
+    #[test]
+    fn invalid_utf8_is_an_error() {
+        let mut buffer = String::new();
+        let err = read_all(Cursor::new(vec![0xff, 0xfe]), &mut buffer).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -1,4 +1,10 @@
-use crate::syntax_analyzer::{Expression, Function, Statement};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::const_eval::eval_const;
+use crate::semantic_analyzer::Type;
+use crate::syntax_analyzer::{Expression, Function, SpannedStatement, Statement};
+use crate::target_code_generator::VMValue;
 
 //
 // ===== INTERMEDIATE REPRESENTATION (IR) STRUCTURES =====
@@ -7,57 +13,251 @@ use crate::syntax_analyzer::{Expression, Function, Statement};
 // Each IR instruction represents a single "low-level" operation.
 // This is similar to three-address code (used in compilers).
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum IRInstr {
     Assign(String, IRValue),               // a = value
     BinaryOp(String, String, String, String), // result = left op right
-    Return(String),
+    UnaryOp(String, String, String), // result = op operand
+    Return(Option<String>), // None returns nothing
+    Label(String),                // jump target
+    Jump(String),                 // goto label
+    JumpIfFalse(String, String),  // if !cond goto label
+    Call(String, String, Vec<String>), // result = name(args...)
+    MakeArray(String, Vec<String>), // result = [elements...]
+    Index(String, String, String), // result = base[index]
+    Phi(String, Vec<String>), // result = whichever source came from the predecessor block control arrived from, only in SSA form
 }
 
 // Values used in IR instructions.
 // They can be literals, variable names, or temporary registers.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum IRValue {
     Int(i64),
+    Float(f64),
     Bool(bool),
     Str(String),
+    Char(char),
     Var(String),
-    Temp(String), // temporary variable (like t1, t2)
+    Temp(String), // temporary variable (like %1, %2)
+}
+
+impl IRInstr {
+    // the name an instruction writes (if any) and the names it reads
+    pub fn defs_and_uses(&self) -> (Option<&String>, Vec<&String>) {
+        match self {
+            IRInstr::Assign(target, IRValue::Var(src) | IRValue::Temp(src)) => (Some(target), vec![src]),
+            IRInstr::Assign(target, _) => (Some(target), Vec::new()),
+            IRInstr::BinaryOp(target, l, _, r) => (Some(target), vec![l, r]),
+            IRInstr::UnaryOp(target, _, operand) => (Some(target), vec![operand]),
+            IRInstr::Call(target, _, args) => (Some(target), args.iter().collect()),
+            IRInstr::MakeArray(target, elements) => (Some(target), elements.iter().collect()),
+            IRInstr::Index(target, base, index) => (Some(target), vec![base, index]),
+            IRInstr::Phi(target, sources) => (Some(target), sources.iter().collect()),
+            IRInstr::Return(name) => (None, name.iter().collect()),
+            IRInstr::JumpIfFalse(name, _) => (None, vec![name]),
+            IRInstr::Label(_) | IRInstr::Jump(_) => (None, Vec::new()),
+        }
+    }
+}
+
+// Renders IR as three-address code, one instruction per line: %3 = %1 + %2
+impl fmt::Display for IRInstr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IRInstr::Assign(target, value) => write!(f, "{} = {}", target, value),
+            IRInstr::BinaryOp(res, l, op, r) => write!(f, "{} = {} {} {}", res, l, op, r),
+            IRInstr::UnaryOp(res, op, operand) => write!(f, "{} = {}{}", res, op, operand),
+            IRInstr::Return(Some(name)) => write!(f, "return {}", name),
+            IRInstr::Return(None) => write!(f, "return"),
+            IRInstr::Label(label) => write!(f, "{}:", label),
+            IRInstr::Jump(label) => write!(f, "goto {}", label),
+            IRInstr::JumpIfFalse(cond, label) => write!(f, "ifFalse {} goto {}", cond, label),
+            IRInstr::Call(res, name, args) => write!(f, "{} = {}({})", res, name, args.join(", ")),
+            IRInstr::MakeArray(res, elements) => write!(f, "{} = [{}]", res, elements.join(", ")),
+            IRInstr::Index(res, base, index) => write!(f, "{} = {}[{}]", res, base, index),
+            IRInstr::Phi(res, sources) => write!(f, "{} = phi({})", res, sources.join(", ")),
+        }
+    }
+}
+
+// whether a name is one of the temps new_temp hands out (%1, %2, ...), not a
+// variable from the source, which can never contain a '%'
+pub fn is_temp_name(name: &str) -> bool {
+    name.strip_prefix('%').is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+impl IRValue {
+    // the IR literal for a constant, arrays have no literal form so they stay as code
+    pub fn immediate(value: VMValue) -> Option<IRValue> {
+        match value {
+            VMValue::Int(n) => Some(IRValue::Int(n)),
+            VMValue::Float(n) => Some(IRValue::Float(n)),
+            VMValue::Bool(b) => Some(IRValue::Bool(b)),
+            VMValue::Str(s) => Some(IRValue::Str(s)),
+            VMValue::Char(c) => Some(IRValue::Char(c)),
+            VMValue::Array(_) => None,
+        }
+    }
+
+    // the value a literal stands for, names have none until runtime
+    pub fn constant(&self) -> Option<VMValue> {
+        match self {
+            IRValue::Int(n) => Some(VMValue::Int(*n)),
+            IRValue::Float(n) => Some(VMValue::Float(*n)),
+            IRValue::Bool(b) => Some(VMValue::Bool(*b)),
+            IRValue::Str(s) => Some(VMValue::Str(s.clone())),
+            IRValue::Char(c) => Some(VMValue::Char(*c)),
+            IRValue::Var(_) | IRValue::Temp(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for IRValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IRValue::Int(n) => write!(f, "{}", n),
+            IRValue::Float(n) => write!(f, "{:?}", n),
+            IRValue::Bool(b) => write!(f, "{}", b),
+            IRValue::Str(s) => write!(f, "{:?}", s),
+            IRValue::Char(c) => write!(f, "{:?}", c),
+            IRValue::Var(name) | IRValue::Temp(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+// Whole listing, labels sit flush left and everything else is indented under them
+pub fn format_ir(code: &[IRInstr]) -> String {
+    let mut out = String::new();
+    for instr in code {
+        if !matches!(instr, IRInstr::Label(_)) {
+            out.push_str("    ");
+        }
+        out.push_str(&instr.to_string());
+        out.push('\n');
+    }
+    out
+}
+
+// A function's IR along with what lowering needs to lay it out and call it.
+#[derive(Debug, Clone)]
+pub struct IRFunction {
+    pub name: String,
+    pub params: Vec<(String, Type)>,
+    pub return_type: Type, // Unit when not declared
+    pub code: Vec<IRInstr>,
 }
 
 // Holds state for generating IR — mainly the temp counter and the list of instructions.
 
 pub struct IRGenerator {
-    temp_counter: usize, //counter to create unique temps such as t1, t2, t3 ...
+    temp_counter: usize, //counter to create unique temps such as %1, %2, %3 ...
+    label_counter: usize, //counter to create unique labels such as L1, L2, L3 ...
     code: Vec<IRInstr>, //List of the generated instructions
+    scopes: Vec<HashMap<String, String>>, //source var name -> IR name, innermost last
+    shadow_counter: usize, //counter to rename shadowing vars such as x.1, x.2 ...
+    loops: Vec<(String, String)>, //(continue, break) labels of the loops we're inside, innermost last
+}
+
+impl Default for IRGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl IRGenerator {
     pub fn new() -> Self {
         Self {
             temp_counter: 0,
+            label_counter: 0,
             code: Vec::new(),
+            scopes: vec![HashMap::new()],
+            shadow_counter: 0,
+            loops: Vec::new(),
         }
     }
 
-    //generates temp variable name
+    //all vars live in one VM frame, so a var that shadows an outer one gets a
+    //new IR name (x.1) instead of overwriting it
+    fn declare_var(&mut self, name: &str) -> String {
+        let ir_name = if self.lookup_var(name).is_some() {
+            self.shadow_counter += 1;
+            format!("{}.{}", name, self.shadow_counter)
+        } else {
+            name.to_string()
+        };
+        let scope = self.scopes.last_mut().expect("No scope");
+        scope.insert(name.to_string(), ir_name.clone());
+        ir_name
+    }
+
+    //IR name a source var currently refers to
+    fn lookup_var(&self, name: &str) -> Option<&String> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    //generates the statements of a block in their own scope
+    fn generate_block(&mut self, stmts: &[SpannedStatement]) {
+        self.scopes.push(HashMap::new());
+        for stmt in stmts {
+            self.generate_statement(&stmt.stmt);
+        }
+        self.scopes.pop();
+    }
+
+    //generates temp variable name, the '%' keeps it apart from any source variable
     fn new_temp(&mut self) -> String {
         self.temp_counter += 1;
-        format!("t{}", self.temp_counter)
+        format!("%{}", self.temp_counter)
+    }
+
+    //generates label name
+    fn new_label(&mut self) -> String {
+        self.label_counter += 1;
+        format!("L{}", self.label_counter)
+    }
+
+    //instructions only take names, so literals get stored into a temp first
+    fn operand_name(&mut self, val: IRValue) -> String {
+        match val {
+            //if already a variable or temp then use it directly
+            IRValue::Var(v) | IRValue::Temp(v) => v,
+            IRValue::Int(_) | IRValue::Float(_) | IRValue::Bool(_) | IRValue::Str(_) | IRValue::Char(_) => {
+                let lit = self.new_temp();
+                self.code.push(IRInstr::Assign(lit.clone(), val));
+                lit
+            }
+        }
     }
 
     //
     // ===== MAIN ENTRY POINT =====
     //
     // Converts a full parsed function into a vector of IR instructions.
+    // The generator is left empty afterwards, ready for another function.
     //
     pub fn generate_function(&mut self, func: &Function) -> Vec<IRInstr> {
+        self.emit_function(func);
+        std::mem::take(self).finish()
+    }
+
+    // Appends the function's IR to the code generated so far
+    pub fn emit_function(&mut self, func: &Function) {
+        for (param, _) in &func.params {
+            self.declare_var(param);
+        }
 
         for stmt in &func.body {
-            self.generate_statement(stmt);
+            self.generate_statement(&stmt.stmt);
+        }
+        //a function without a return type may run off the end of its body
+        if func.return_type.is_none() {
+            self.code.push(IRInstr::Return(None));
         }
-        //return full ir code
-        self.code.clone()
+    }
+
+    // The generated code, moved out instead of copied
+    pub fn finish(self) -> Vec<IRInstr> {
+        self.code
     }
 
     //
@@ -66,23 +266,32 @@ impl IRGenerator {
     fn generate_statement(&mut self, stmt: &Statement) {
         match stmt {
             //handels var declarations
-            Statement::VarDecl { name, value } => {
+            Statement::VarDecl { name, value, .. } => {
                 let val = self.generate_expression(value);
 
                 //adds assignment instruction
-                self.code.push(IRInstr::Assign(name.clone(), val));
+                let ir_name = self.declare_var(name);
+                self.code.push(IRInstr::Assign(ir_name, val));
+            }
+
+            //writes the var the name currently refers to
+            Statement::Assign { name, value } => {
+                let val = self.generate_expression(value);
+                let ir_name = self.lookup_var(name).unwrap_or(name).clone();
+                self.code.push(IRInstr::Assign(ir_name, val));
             }
 
             //handels return statements
-            Statement::Return(expr) => {
+            Statement::Return(None) => self.code.push(IRInstr::Return(None)),
+            Statement::Return(Some(expr)) => {
                 let val = self.generate_expression(expr);
                 if let IRValue::Temp(t) | IRValue::Var(t) = val {
-                    self.code.push(IRInstr::Return(t));
+                    self.code.push(IRInstr::Return(Some(t)));
                 } else {
                     // return literal directly
                     let tmp = self.new_temp();
                     self.code.push(IRInstr::Assign(tmp.clone(), val));
-                    self.code.push(IRInstr::Return(tmp));
+                    self.code.push(IRInstr::Return(Some(tmp)));
                 }
             }
 
@@ -91,19 +300,114 @@ impl IRGenerator {
             Statement::Expr(expr) => {
                 self.generate_expression(expr);
             }
+
+            //   JumpIfFalse cond, else
+            //   <then>
+            //   Jump end
+            // else:
+            //   <else>
+            // end:
+            Statement::If { cond, then_body, else_body } => {
+                let val = self.generate_expression(cond);
+                let cond_name = self.operand_name(val);
+                let else_label = self.new_label();
+                let end_label = self.new_label();
+
+                self.code.push(IRInstr::JumpIfFalse(cond_name, else_label.clone()));
+                self.generate_block(then_body);
+                self.code.push(IRInstr::Jump(end_label.clone()));
+
+                self.code.push(IRInstr::Label(else_label));
+                if let Some(else_body) = else_body {
+                    self.generate_block(else_body);
+                }
+                self.code.push(IRInstr::Label(end_label));
+            }
+
+            // start:
+            //   JumpIfFalse cond, end
+            //   <body>
+            //   Jump start
+            // end:
+            Statement::While { cond, body } => {
+                let start_label = self.new_label();
+                let end_label = self.new_label();
+
+                self.code.push(IRInstr::Label(start_label.clone()));
+                let val = self.generate_expression(cond);
+                let cond_name = self.operand_name(val);
+                self.code.push(IRInstr::JumpIfFalse(cond_name, end_label.clone()));
+
+                self.loops.push((start_label.clone(), end_label.clone()));
+                self.generate_block(body);
+                self.loops.pop();
+
+                self.code.push(IRInstr::Jump(start_label));
+                self.code.push(IRInstr::Label(end_label));
+            }
+
+            //the semantic analyzer already made sure these are inside a loop
+            Statement::Break | Statement::Continue => {
+                let (continue_label, break_label) = self.loops.last().expect("break/continue outside a loop");
+                let label = if matches!(stmt, Statement::Break) { break_label } else { continue_label };
+                self.code.push(IRInstr::Jump(label.clone()));
+            }
+
+            //no jumps needed, the block's scope only changes which IR names vars get
+            Statement::Block(body) => self.generate_block(body),
         }
     }
 
     fn generate_expression(&mut self, expr: &Expression) -> IRValue {
+        //operations on literals only are worked out now and emitted as an immediate
+        if matches!(expr, Expression::BinaryOp { .. } | Expression::Unary { .. } | Expression::Index { .. }) {
+            if let Some(value) = eval_const(expr).and_then(IRValue::immediate) {
+                return value;
+            }
+        }
+
         match expr {
             // Literal values become immediate IR values
 
             Expression::Integer(n) => IRValue::Int(*n),
+            Expression::Float(n) => IRValue::Float(*n),
             Expression::Boolean(b) => IRValue::Bool(*b),
             Expression::String(s) => IRValue::Str(s.clone()),
+            Expression::Char(c) => IRValue::Char(*c),
             // Variable name -> IR variable reference
 
-            Expression::Ident(name) => IRValue::Var(name.clone()),
+            Expression::Ident(name) => {
+                let ir_name = self.lookup_var(name).unwrap_or(name);
+                IRValue::Var(ir_name.clone())
+            }
+
+            //the right side only runs when the left doesn't already decide it
+            //   tmp = left                tmp = left
+            //   JumpIfFalse tmp, end      JumpIfFalse tmp, rhs
+            //   tmp = right               Jump end
+            // end:                      rhs:
+            //                             tmp = right
+            //                           end:
+            Expression::BinaryOp { left, op, right } if op == "&&" || op == "||" => {
+                let left_val = self.generate_expression(left);
+                let tmp = self.new_temp();
+                let end_label = self.new_label();
+                self.code.push(IRInstr::Assign(tmp.clone(), left_val));
+
+                if op == "&&" {
+                    self.code.push(IRInstr::JumpIfFalse(tmp.clone(), end_label.clone()));
+                } else {
+                    let rhs_label = self.new_label();
+                    self.code.push(IRInstr::JumpIfFalse(tmp.clone(), rhs_label.clone()));
+                    self.code.push(IRInstr::Jump(end_label.clone()));
+                    self.code.push(IRInstr::Label(rhs_label));
+                }
+
+                let right_val = self.generate_expression(right);
+                self.code.push(IRInstr::Assign(tmp.clone(), right_val));
+                self.code.push(IRInstr::Label(end_label));
+                IRValue::Temp(tmp)
+            }
 
             Expression::BinaryOp { left, op, right } => {
                 //recursivly generate code for both sides
@@ -111,28 +415,53 @@ impl IRGenerator {
                 let right_val = self.generate_expression(right);
                 let tmp = self.new_temp();
 
-                let l = match left_val {
-                    //if already a variable or temp then use it directly
-                    IRValue::Var(ref v) | IRValue::Temp(ref v) => v.clone(),
-                    IRValue::Int(_) | IRValue::Bool(_) | IRValue::Str(_) => {
-                        let lit = self.new_temp();
-                        self.code.push(IRInstr::Assign(lit.clone(), left_val));
-                        lit
-                    }
-                };
-                let r = match right_val {
-                    IRValue::Var(ref v) | IRValue::Temp(ref v) => v.clone(),
-                    IRValue::Int(_) | IRValue::Bool(_) | IRValue::Str(_) => {
-                        let lit = self.new_temp();
-                        self.code.push(IRInstr::Assign(lit.clone(), right_val));
-                        lit
-                    }
-                };
+                let l = self.operand_name(left_val);
+                let r = self.operand_name(right_val);
                 //add to the actaul binary operation instructions
                 self.code.push(IRInstr::BinaryOp(tmp.clone(), l, op.clone(), r));
                 IRValue::Temp(tmp)
             }
+
+            Expression::Unary { op, operand } => {
+                let val = self.generate_expression(operand);
+                let name = self.operand_name(val);
+                let tmp = self.new_temp();
+                self.code.push(IRInstr::UnaryOp(tmp.clone(), op.clone(), name));
+                IRValue::Temp(tmp)
+            }
+
+            //evaluate arguments left to right, then call
+            Expression::Call { name, args } => {
+                let mut arg_names = Vec::new();
+                for arg in args {
+                    let val = self.generate_expression(arg);
+                    arg_names.push(self.operand_name(val));
+                }
+                let tmp = self.new_temp();
+                self.code.push(IRInstr::Call(tmp.clone(), name.clone(), arg_names));
+                IRValue::Temp(tmp)
+            }
+
+            Expression::Array(elements) => {
+                let mut elem_names = Vec::new();
+                for elem in elements {
+                    let val = self.generate_expression(elem);
+                    elem_names.push(self.operand_name(val));
+                }
+                let tmp = self.new_temp();
+                self.code.push(IRInstr::MakeArray(tmp.clone(), elem_names));
+                IRValue::Temp(tmp)
+            }
+
+            Expression::Index { base, index } => {
+                let base_val = self.generate_expression(base);
+                let base_name = self.operand_name(base_val);
+                let index_val = self.generate_expression(index);
+                let index_name = self.operand_name(index_val);
+                let tmp = self.new_temp();
+                self.code.push(IRInstr::Index(tmp.clone(), base_name, index_name));
+                IRValue::Temp(tmp)
+            }
         }
     }
 }
-
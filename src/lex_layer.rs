@@ -1,113 +1,577 @@
 use std::cmp::PartialEq;
-
-fn create_vec() -> Vec<Token> {
-    let input_code: Vec<Token> = Vec::new();
-    return input_code;
-}
-//the above creates a vector to use
+use std::fmt;
+use std::iter::Peekable;
+use std::str::Chars;
 
 //this is for all the types of tokens there can be in the language
 #[derive(Debug, PartialEq)]
 pub enum Token{
-    Keyword(String), // e.g., func, if, for, var, return
+    Keyword(String), // e.g., func, if, for, var, const, return
     Identifier(String), // e.g., "my_variable", "function_name"
     Literal(LiteralType), // e.g., numbers, strings, booleans
     Operator(String), // e.g., "+", "-", "="
-    Delimiter(char), // e.g., "(", "{", ";"
+    Delimiter(char), // e.g., "(", "{", ";", ":"
     Whitespace,
-    Comment,
-    Unknown(char),
+    Comment(String), // text between the comment markers
+    Eof, // always the last token, sits just past the end of the source
 }
 
 //This is for the different types of variables there can be
 #[derive(Debug, PartialEq)]
 pub enum LiteralType {
     Integer(i64),
+    Float(f64),
     Boolean(bool),
     String(String),
+    Char(char),
 }
 
 
 
-//uses tokens and categorizes them
-//input and is_whitespace is giving issues.
-pub fn tokenize<E>(input: Result<&str, E>) -> Result<Vec<Token>, E> {
-    let s = input?; // if Err(E), return it immediately
-    let mut tokens = Vec::new();
-    let mut chars = s.chars().peekable();
+//renders a token as the source text it came from, so func main() { ... }
+//tokens print back as func main() { ... }. Whitespace is a single space and
+//comments come back as /* */ block comments
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Keyword(s) | Token::Identifier(s) => write!(f, "{}", s),
+            Token::Literal(LiteralType::Integer(n)) => write!(f, "{}", n),
+            Token::Literal(LiteralType::Float(n)) => write!(f, "{:?}", n),
+            Token::Literal(LiteralType::Boolean(b)) => write!(f, "{}", if *b { "yeah" } else { "nah" }),
+            Token::Literal(LiteralType::String(s)) => write!(f, "\"{}\"", s),
+            Token::Literal(LiteralType::Char(c)) => match c {
+                '\n' => write!(f, "'\\n'"),
+                '\t' => write!(f, "'\\t'"),
+                '\r' => write!(f, "'\\r'"),
+                '\0' => write!(f, "'\\0'"),
+                '\\' | '\'' => write!(f, "'\\{}'", c),
+                _ => write!(f, "'{}'", c),
+            },
+            Token::Operator(op) => write!(f, "{}", op),
+            Token::Delimiter(c) => write!(f, "{}", c),
+            Token::Whitespace => write!(f, " "),
+            Token::Comment(text) => write!(f, "/*{}*/", text),
+            Token::Eof => Ok(()),
+        }
+    }
+}
+
+//the tokens back to back as source text. Layout is lost, but lexing the
+//result gives the same tokens again
+pub fn tokens_to_source(tokens: &[SpannedToken]) -> String {
+    tokens.iter().map(|tok| tok.token.to_string()).collect()
+}
 
-    while let Some(&c) = chars.peek() {
-        match c {
-            _ if c.is_whitespace() => {
-                chars.next();
-                if !tokens.last().map_or(false, |t| t == &Token::Whitespace) {
-                    tokens.push(Token::Whitespace);
-                }
+//short constructors for writing out expected tokens by hand, mostly used
+//through the tokens! macro below
+impl Token {
+    pub fn kw(s: &str) -> Token {
+        Token::Keyword(s.to_string())
+    }
+
+    pub fn ident(s: &str) -> Token {
+        Token::Identifier(s.to_string())
+    }
+
+    pub fn op(s: &str) -> Token {
+        Token::Operator(s.to_string())
+    }
+
+    pub fn delim(c: char) -> Token {
+        Token::Delimiter(c)
+    }
+
+    pub fn int(n: i64) -> Token {
+        Token::Literal(LiteralType::Integer(n))
+    }
+
+    pub fn float(n: f64) -> Token {
+        Token::Literal(LiteralType::Float(n))
+    }
+
+    pub fn boolean(b: bool) -> Token {
+        Token::Literal(LiteralType::Boolean(b))
+    }
+
+    pub fn string(s: &str) -> Token {
+        Token::Literal(LiteralType::String(s.to_string()))
+    }
+
+    pub fn char(c: char) -> Token {
+        Token::Literal(LiteralType::Char(c))
+    }
+
+    pub fn ws() -> Token {
+        Token::Whitespace
+    }
+
+    pub fn eof() -> Token {
+        Token::Eof
+    }
+}
+
+//builds a Vec<Token> out of the constructors above, an argument-less one can
+//drop its parens. `var x = 5;` lexes to
+//    tokens![kw("var"), ws, ident("x"), ws, op("="), ws, int(5), delim(';'), eof]
+//and a lexer check is then
+//    assert!(tokens_equal(&strip_spans(tokenize("var x = 5;")?), &tokens![...]));
+#[macro_export]
+macro_rules! tokens {
+    ($($kind:ident $(($($arg:expr),*))?),* $(,)?) => {
+        vec![$($crate::lex_layer::Token::$kind($($($arg),*)?)),*]
+    };
+}
+
+//true if both streams have the same tokens in the same order
+pub fn tokens_equal(a: &[Token], b: &[Token]) -> bool {
+    a == b
+}
+
+//just the tokens, for comparing against a stream written without positions
+pub fn strip_spans(tokens: Vec<SpannedToken>) -> Vec<Token> {
+    tokens.into_iter().map(|tok| tok.token).collect()
+}
+
+//a token along with where it starts in the source file
+#[derive(Debug, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub line: usize,
+    pub col: usize,
+}
+
+//why the source couldn't be turned into tokens, line and col are where the bad token starts
+#[derive(Debug, PartialEq)]
+pub enum LexError {
+    UnterminatedString { line: usize, col: usize },
+    UnterminatedComment { line: usize, col: usize },
+    NumberOutOfRange { literal: String, line: usize, col: usize },
+    BadNumber { literal: String, line: usize, col: usize }, // a 0x/0o/0b with no digits or one outside its base
+    UnexpectedChar { ch: char, line: usize, col: usize }, // a char that can't start any token
+    InvalidChar { line: usize, col: usize }, // empty, more than one char, unknown escape or no closing quote
+    MisplacedUnderscore { literal: String, line: usize, col: usize }, // a '_' that isn't between two digits
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::UnterminatedString { line, col } => {
+                write!(f, "unterminated string starting at line {}, col {}", line, col)
             }
-            '/' if chars.clone().nth(1) == Some('/') => {
-                while let Some(ch) = chars.next() {
-                    if ch == '\n' {
-                        break;
-                    }
-                }
-                tokens.push(Token::Comment);
+            LexError::UnterminatedComment { line, col } => {
+                write!(f, "unterminated block comment starting at line {}, col {}", line, col)
             }
-            '+' | '-' | '*' | '/' | '=' => {
-                tokens.push(Token::Operator(c.to_string()));
-                chars.next();
+            LexError::NumberOutOfRange { literal, line, col } => write!(
+                f,
+                "number {} at line {}, col {} is out of range, integers must fit in 64 bits",
+                literal, line, col
+            ),
+            LexError::BadNumber { literal, line, col } => write!(
+                f,
+                "number {} at line {}, col {} isn't valid, 0x takes 0-9 and a-f, 0o 0-7 and 0b 0-1",
+                literal, line, col
+            ),
+            LexError::UnexpectedChar { ch, line, col } => {
+                write!(f, "unexpected character {:?} at line {}, col {}", ch, line, col)
             }
-            '(' | ')' | '{' | '}' | ';' => {
-                tokens.push(Token::Delimiter(c));
-                chars.next();
+            LexError::MisplacedUnderscore { literal, line, col } => write!(
+                f,
+                "number {} at line {}, col {} has a misplaced '_', underscores can only go between two digits like 1_000",
+                literal, line, col
+            ),
+            LexError::InvalidChar { line, col } => write!(
+                f,
+                "char literal at line {}, col {} must be exactly one character like 'a' or '\\n'",
+                line, col
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LexError {}
+
+impl LexError {
+    //line and col of where the bad token starts
+    pub fn position(&self) -> (usize, usize) {
+        match self {
+            LexError::UnterminatedString { line, col }
+            | LexError::UnterminatedComment { line, col }
+            | LexError::NumberOutOfRange { line, col, .. }
+            | LexError::BadNumber { line, col, .. }
+            | LexError::UnexpectedChar { line, col, .. }
+            | LexError::InvalidChar { line, col }
+            | LexError::MisplacedUnderscore { line, col, .. } => (*line, *col),
+        }
+    }
+}
+
+//walks the source one char at a time and keeps track of the line and column
+struct Cursor<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    col: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(s: &'a str) -> Self {
+        Self { chars: s.chars().peekable(), line: 1, col: 1 }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    //looks one char past peek without consuming anything
+    fn peek_second(&self) -> Option<char> {
+        self.chars.clone().nth(1)
+    }
+
+    //\n, \r\n and a lone \r each end a line. The \r of a \r\n leaves the
+    //position alone and the \n after it moves to the next line
+    fn bump(&mut self) -> Option<char> {
+        let ch = self.chars.next()?;
+        match ch {
+            '\r' if self.peek() == Some('\n') => {}
+            '\n' | '\r' => {
+                self.line += 1;
+                self.col = 1;
             }
-            _ if c.is_alphabetic() || c == '_' => {
-                let mut ident_str = String::new();
-                while let Some(&ch) = chars.peek() {
-                    if ch.is_alphanumeric() || ch == '_' {
-                        ident_str.push(chars.next().unwrap());
-                    } else {
-                        break;
+            _ => self.col += 1,
+        }
+        Some(ch)
+    }
+}
+
+//uses tokens and categorizes them, the whole source at once
+pub fn tokenize(s: &str) -> Result<Vec<SpannedToken>, LexError> {
+    Lexer::new(s).collect()
+}
+
+//produces tokens one at a time as they're asked for. The last one is Eof,
+//or the first error if the source can't be lexed
+pub struct Lexer<'a> {
+    chars: Cursor<'a>,
+    last_was_whitespace: bool, //a run of whitespace only makes one token
+    done: bool,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(s: &'a str) -> Self {
+        Self { chars: Cursor::new(s), last_was_whitespace: false, done: false }
+    }
+
+    fn next_token(&mut self) -> Result<SpannedToken, LexError> {
+        let chars = &mut self.chars;
+        while let Some(c) = chars.peek() {
+            //remember where the token starts before consuming it
+            let (line, col) = (chars.line, chars.col);
+            let token = match c {
+                _ if c.is_whitespace() => {
+                    chars.bump();
+                    if self.last_was_whitespace {
+                        continue;
                     }
+                    Token::Whitespace
                 }
-                match ident_str.as_str() {
-                    "func" | "var" | "if" | "else" | "return" => tokens.push(Token::Keyword(ident_str)),
-                    "yeah" => tokens.push(Token::Literal(LiteralType::Boolean(true))),
-                    "nah" => tokens.push(Token::Literal(LiteralType::Boolean(false))),
-                    _ => tokens.push(Token::Identifier(ident_str)),
+                '/' if chars.peek_second() == Some('/') => {
+                    chars.bump();
+                    chars.bump();
+                    let mut text = String::new();
+                    while let Some(ch) = chars.bump() {
+                        if ch == '\n' || ch == '\r' {
+                            //the \n of a \r\n belongs to the same line break
+                            if ch == '\r' && chars.peek() == Some('\n') {
+                                chars.bump();
+                            }
+                            break;
+                        }
+                        text.push(ch);
+                    }
+                    Token::Comment(text)
                 }
-            }
-            _ if c.is_ascii_digit() => {
-                let mut num_str = String::new();
-                while let Some(&ch) = chars.peek() {
-                    if ch.is_ascii_digit() {
-                        num_str.push(chars.next().unwrap());
+                //block comments nest, so /* a /* b */ c */ is one comment
+                '/' if chars.peek_second() == Some('*') => {
+                    chars.bump();
+                    chars.bump();
+                    let mut text = String::new();
+                    let mut depth = 1;
+                    loop {
+                        match chars.bump() {
+                            Some('/') if chars.peek() == Some('*') => {
+                                chars.bump();
+                                depth += 1;
+                                text.push_str("/*");
+                            }
+                            Some('*') if chars.peek() == Some('/') => {
+                                chars.bump();
+                                depth -= 1;
+                                if depth == 0 {
+                                    break;
+                                }
+                                text.push_str("*/");
+                            }
+                            Some(ch) => text.push(ch),
+                            None => return Err(LexError::UnterminatedComment { line, col }),
+                        }
+                    }
+                    Token::Comment(text)
+                }
+                //-> points at a function's return type
+                '-' if chars.peek_second() == Some('>') => {
+                    chars.bump();
+                    chars.bump();
+                    Token::Operator("->".to_string())
+                }
+                '+' | '-' | '*' | '/' | '%' => {
+                    chars.bump();
+                    Token::Operator(c.to_string())
+                }
+                //these can be followed by '=' to make ==, !=, <= and >=
+                '=' | '!' | '<' | '>' => {
+                    chars.bump();
+                    let mut op = c.to_string();
+                    if chars.peek() == Some('=') {
+                        op.push('=');
+                        chars.bump();
+                    }
+                    Token::Operator(op)
+                }
+                //&& and ||, a single & or | isn't an operator
+                '&' | '|' if chars.peek_second() == Some(c) => {
+                    chars.bump();
+                    chars.bump();
+                    Token::Operator(format!("{}{}", c, c))
+                }
+                '(' | ')' | '{' | '}' | '[' | ']' | ';' | ',' | ':' => {
+                    chars.bump();
+                    Token::Delimiter(c)
+                }
+                _ if c.is_alphabetic() || c == '_' => {
+                    let mut ident_str = String::new();
+                    while let Some(ch) = chars.peek() {
+                        if ch.is_alphanumeric() || ch == '_' {
+                            ident_str.push(ch);
+                            chars.bump();
+                        } else {
+                            break;
+                        }
+                    }
+                    match ident_str.as_str() {
+                        "func" | "var" | "const" | "if" | "else" | "while" | "break" | "continue" | "return" => Token::Keyword(ident_str),
+                        "yeah" => Token::Literal(LiteralType::Boolean(true)),
+                        "nah" => Token::Literal(LiteralType::Boolean(false)),
+                        _ => Token::Identifier(ident_str),
+                    }
+                }
+                //0x, 0o and 0b prefixes pick the base, a plain 0 stays base 10
+                '0' if matches!(chars.peek_second(), Some('x' | 'o' | 'b')) => {
+                    chars.bump();
+                    let prefix = chars.bump().unwrap_or('b');
+                    let radix = match prefix {
+                        'x' => 16,
+                        'o' => 8,
+                        _ => 2,
+                    };
+                    let mut num_str = String::new();
+                    let mut bad_digit = None;
+                    while let Some(ch) = chars.peek() {
+                        if ch == '_' {
+                            num_str.push(ch);
+                            chars.bump();
+                        } else if ch.is_ascii_alphanumeric() {
+                            if !ch.is_digit(radix) && bad_digit.is_none() {
+                                bad_digit = Some(ch);
+                            }
+                            num_str.push(ch);
+                            chars.bump();
+                        } else {
+                            break;
+                        }
+                    }
+                    let literal = format!("0{}{}", prefix, num_str);
+                    let Some(num_str) = strip_underscores(&num_str) else {
+                        return Err(LexError::MisplacedUnderscore { literal, line, col });
+                    };
+                    if bad_digit.is_some() || num_str.is_empty() {
+                        return Err(LexError::BadNumber { literal, line, col });
+                    }
+                    match i64::from_str_radix(&num_str, radix) {
+                        Ok(num) => Token::Literal(LiteralType::Integer(num)),
+                        Err(_) => return Err(LexError::NumberOutOfRange { literal, line, col }),
+                    }
+                }
+                //underscores can separate digits, 1_000_000 is 1000000
+                _ if c.is_ascii_digit() => {
+                    let mut num_str = String::new();
+                    while let Some(ch) = chars.peek().filter(|ch| ch.is_ascii_digit() || *ch == '_') {
+                        num_str.push(ch);
+                        chars.bump();
+                    }
+                    let int_part = num_str.clone();
+                    //a '.' followed by a digit makes it a float like 2.5, both sides need digits
+                    let is_float = chars.peek() == Some('.') && chars.peek_second().is_some_and(|ch| ch.is_ascii_digit());
+                    let mut frac_part = String::new();
+                    if is_float {
+                        chars.bump();
+                        while let Some(ch) = chars.peek().filter(|ch| ch.is_ascii_digit() || *ch == '_') {
+                            frac_part.push(ch);
+                            chars.bump();
+                        }
+                        num_str = format!("{}.{}", num_str, frac_part);
+                    }
+                    let (Some(int_digits), Some(frac_digits)) =
+                        (strip_underscores(&int_part), strip_underscores(&frac_part))
+                    else {
+                        return Err(LexError::MisplacedUnderscore { literal: num_str, line, col });
+                    };
+                    if is_float {
+                        let num = format!("{}.{}", int_digits, frac_digits)
+                            .parse::<f64>()
+                            .expect("digits, a '.' and digits always parse");
+                        Token::Literal(LiteralType::Float(num))
                     } else {
-                        break;
+                        match int_digits.parse::<i64>() {
+                            Ok(num) => Token::Literal(LiteralType::Integer(num)),
+                            Err(_) => return Err(LexError::NumberOutOfRange { literal: num_str, line, col }),
+                        }
                     }
                 }
-                if let Ok(num) = num_str.parse::<i64>() {
-                    tokens.push(Token::Literal(LiteralType::Integer(num)));
-                } else {
-                    tokens.push(Token::Unknown(c));
+                '"' => {
+                    chars.bump();
+                    let mut string_content = String::new();
+                    loop {
+                        match chars.bump() {
+                            Some('"') => break,
+                            Some(ch) => string_content.push(ch),
+                            None => return Err(LexError::UnterminatedString { line, col }),
+                        }
+                    }
+                    Token::Literal(LiteralType::String(string_content))
                 }
-            }
-            '"' => {
-                chars.next();
-                let mut string_content = String::new();
-                while let Some(ch) = chars.next() {
-                    if ch == '"' {
-                        break;
+                //a single character, \ starts an escape like '\n'
+                '\'' => {
+                    chars.bump();
+                    let mut content = Vec::new();
+                    loop {
+                        match chars.bump() {
+                            Some('\'') => break,
+                            Some('\\') => {
+                                let escaped = match chars.bump() {
+                                    Some('n') => '\n',
+                                    Some('t') => '\t',
+                                    Some('r') => '\r',
+                                    Some('0') => '\0',
+                                    Some(ch @ ('\\' | '\'' | '"')) => ch,
+                                    _ => return Err(LexError::InvalidChar { line, col }),
+                                };
+                                content.push(escaped);
+                            }
+                            Some(ch) if ch != '\n' && ch != '\r' => content.push(ch),
+                            _ => return Err(LexError::InvalidChar { line, col }),
+                        }
+                    }
+                    match content[..] {
+                        [ch] => Token::Literal(LiteralType::Char(ch)),
+                        _ => return Err(LexError::InvalidChar { line, col }),
                     }
-                    string_content.push(ch);
                 }
-                tokens.push(Token::Literal(LiteralType::String(string_content)));
-            }
-            _ => {
-                tokens.push(Token::Unknown(c));
-                chars.next();
-            }
+                _ => return Err(LexError::UnexpectedChar { ch: c, line, col }),
+            };
+            self.last_was_whitespace = token == Token::Whitespace;
+            return Ok(SpannedToken { token, line, col });
+        }
+
+        Ok(SpannedToken { token: Token::Eof, line: chars.line, col: chars.col })
+    }
+}
+
+//the digits of a number with its '_' separators taken out, None when an
+//underscore is at the start or end or next to another one
+fn strip_underscores(digits: &str) -> Option<String> {
+    if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return None;
+    }
+    Some(digits.replace('_', ""))
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Result<SpannedToken, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
         }
+        let next = self.next_token();
+        self.done = !matches!(next, Ok(SpannedToken { ref token, .. }) if *token != Token::Eof);
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(src: &str) -> Vec<Token> {
+        strip_spans(tokenize(src).unwrap())
+    }
+
+    #[test]
+    fn comma_separates_parameters() {
+        let expected = tokens![ident("f"), delim('('), ident("a"), delim(','), ws, ident("b"), delim(')'), eof];
+        assert!(tokens_equal(&lex("f(a, b)"), &expected));
     }
 
-    Ok(tokens)
+    #[test]
+    fn var_decl_matches_expected_tokens() {
+        let expected = tokens![kw("var"), ws, ident("x"), ws, op("="), ws, int(5), delim(';'), eof];
+        assert!(tokens_equal(&lex("var x = 5;"), &expected));
+    }
+
+    #[test]
+    fn unterminated_string_points_at_opening_quote() {
+        assert_eq!(tokenize("var s = \"abc").unwrap_err(), LexError::UnterminatedString { line: 1, col: 9 });
+    }
+
+    #[test]
+    fn unterminated_comment_points_at_opening_marker() {
+        assert_eq!(tokenize("x\n  /* a /* b */").unwrap_err(), LexError::UnterminatedComment { line: 2, col: 3 });
+    }
+
+    #[test]
+    fn char_literals_and_escapes() {
+        assert_eq!(lex("'z'"), tokens![char('z'), eof]);
+        assert_eq!(lex("'\\t'"), tokens![char('\t'), eof]);
+        assert_eq!(tokenize("'ab'").unwrap_err(), LexError::InvalidChar { line: 1, col: 1 });
+        assert_eq!(tokenize("''").unwrap_err(), LexError::InvalidChar { line: 1, col: 1 });
+    }
+
+    #[test]
+    fn crlf_ends_a_line_comment() {
+        let tokens = tokenize("// note\r\nreturn").unwrap();
+        assert_eq!(tokens[0].token, Token::Comment(" note".to_string()));
+        assert_eq!(tokens[1], SpannedToken { token: Token::kw("return"), line: 2, col: 1 });
+    }
+
+    #[test]
+    fn lone_cr_counts_as_a_line_break() {
+        let tokens = tokenize("a\rb").unwrap();
+        assert_eq!(tokens[2], SpannedToken { token: Token::ident("b"), line: 2, col: 1 });
+    }
+
+    #[test]
+    fn underscores_between_digits_are_dropped() {
+        assert_eq!(lex("1_000_000"), tokens![int(1_000_000), eof]);
+        assert_eq!(lex("0xff_ff"), tokens![int(0xffff), eof]);
+    }
+
+    #[test]
+    fn misplaced_underscores_are_rejected() {
+        for src in ["1_", "1__0", "0x_ff", "2.5_"] {
+            assert!(
+                matches!(tokenize(src), Err(LexError::MisplacedUnderscore { .. })),
+                "{} should be rejected",
+                src
+            );
+        }
+    }
 }
@@ -0,0 +1,319 @@
+use std::fmt;
+
+use crate::intermediate_code_generator::{IRFunction, IRGenerator};
+use crate::lex_layer::{LexError, SpannedToken, Token};
+use crate::semantic_analyzer::{SemanticAnalyzer, SemanticError, Type, Warning};
+use crate::syntax_analyzer::{Function, ParseError, Parser, ReplLine, SpannedStatement, Statement};
+use crate::target_code_generator::{VMError, VMProgram, VMValue, VM};
+
+pub mod lex_layer;
+pub mod file_translate;
+pub mod syntax_analyzer;
+pub mod ast_json;
+pub mod semantic_analyzer;
+pub mod intermediate_code_generator;
+pub mod optimizer;
+pub mod cfg;
+pub mod ssa;
+pub mod const_eval;
+pub mod target_code_generator;
+pub mod c_backend;
+pub mod register_vm;
+
+//anything that can stop a program from compiling or running, tagged with the stage it came from
+#[derive(Debug)]
+pub enum CompileError {
+    Lex(LexError),
+    Parse(ParseError),
+    Semantic(SemanticError),
+    Lowering(String),
+    Runtime(VMError),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Lex(msg) => write!(f, "Lex error: {}", msg),
+            CompileError::Parse(msg) => write!(f, "Parse error: {}", msg),
+            CompileError::Semantic(msg) => write!(f, "Semantic error: {}", msg),
+            CompileError::Lowering(msg) => write!(f, "Lowering error: {}", msg),
+            CompileError::Runtime(e) => write!(f, "Runtime error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+impl CompileError {
+    //line and col the error points at, when its stage knows one
+    pub fn position(&self) -> Option<(usize, usize)> {
+        match self {
+            CompileError::Lex(e) => Some(e.position()),
+            CompileError::Parse(e) => Some((e.line, e.col)),
+            CompileError::Semantic(e) => e.pos,
+            CompileError::Lowering(_) | CompileError::Runtime(_) => None,
+        }
+    }
+}
+
+impl From<LexError> for CompileError {
+    fn from(e: LexError) -> Self {
+        CompileError::Lex(e)
+    }
+}
+
+//the message followed by the source line it's about with a ^ under the column,
+//the way rustc shows it:
+//    Lex error: unexpected character '@' at line 2, col 11
+//      |
+//    2 | var x = 1 @ 2;
+//      |           ^
+pub fn report_error(source: &str, line: usize, col: usize, msg: &str) -> String {
+    //same line breaks the lexer counts, \n, \r\n or a lone \r
+    let text = source.replace("\r\n", "\n").split(['\n', '\r']).nth(line.saturating_sub(1)).unwrap_or("").to_string();
+    //tabs before the column stay tabs so the caret lines up however wide they show
+    let indent: String = text
+        .chars()
+        .chain(std::iter::repeat(' '))
+        .take(col.saturating_sub(1))
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let gutter = " ".repeat(line.to_string().len());
+    format!("{}\n{} |\n{} | {}\n{} | {}^", msg, gutter, line, text, gutter, indent)
+}
+
+//runs the whole pipeline on a program and returns what main returned
+pub fn compile(source: &str) -> Result<Option<VMValue>, CompileError> {
+    let tokens = lex_layer::tokenize(source)?;
+    let funcs = parse(&tokens)?;
+    let program = build_program(&funcs)?;
+    VM::new().run_to_end(&program).map_err(CompileError::Runtime)
+}
+
+//runs one REPL line, a bare expression gives back its value
+pub fn eval_line(line: &str) -> Result<Option<VMValue>, CompileError> {
+    let program = repl_program(line)?;
+    VM::new().run_to_end(&program).map_err(CompileError::Runtime)
+}
+
+//compiles a REPL line as the body of a main function, a bare expression
+//becomes `return expr;` so running it gives back the value
+pub fn repl_program(line: &str) -> Result<VMProgram, CompileError> {
+    let tokens = lex_layer::tokenize(line)?;
+    let repl_line = Parser::new(&tokens).parse_repl_line().map_err(CompileError::Parse)?;
+    //errors about the line as a whole point at where it starts
+    let (line, col) = tokens
+        .iter()
+        .find(|tok| !matches!(tok.token, Token::Whitespace | Token::Comment(_)))
+        .map_or((1, 1), |tok| (tok.line, tok.col));
+    let main = match repl_line {
+        ReplLine::Expr(expr) => {
+            let ty = SemanticAnalyzer::new()
+                .expression_type(&expr)
+                .map_err(|e| CompileError::Semantic(SemanticError { msg: e, pos: Some((line, col)) }))?;
+            //an expression with no value, like a print, just runs
+            let (return_type, stmt) = if ty == Type::Unit {
+                (None, Statement::Expr(expr))
+            } else {
+                (Some(ty), Statement::Return(Some(expr)))
+            };
+            let body = vec![SpannedStatement { stmt, line, col }];
+            Function { name: "main".to_string(), params: Vec::new(), return_type, body, line, col }
+        }
+        ReplLine::Statements(body) => {
+            Function { name: "main".to_string(), params: Vec::new(), return_type: None, body, line, col }
+        }
+    };
+    build_program(&[main])
+}
+
+//checks the functions and compiles them all the way down to VM code
+pub fn build_program(funcs: &[Function]) -> Result<VMProgram, CompileError> {
+    analyze(funcs)?;
+
+    let mut ir_funcs: Vec<IRFunction> = generate_ir(funcs);
+    let opt = optimizer::Optimizer::new().build();
+    for func in &mut ir_funcs {
+        opt.run_function(func);
+    }
+
+    let program = target_code_generator::lower_program(&ir_funcs, "main").map_err(CompileError::Lowering)?;
+    Ok(target_code_generator::peephole(program))
+}
+
+pub fn parse(tokens: &[SpannedToken]) -> Result<Vec<Function>, CompileError> {
+    Parser::new(tokens).parse_program().map_err(CompileError::Parse)
+}
+
+//checks every function and that there is a main to start at,
+//returns the warnings paired with the function they came from
+pub fn analyze(funcs: &[Function]) -> Result<Vec<(String, Warning)>, CompileError> {
+    let mut all_warnings = Vec::new();
+
+    //every signature is known before any body is checked, so functions can call
+    //each other in any order
+    let mut sema = SemanticAnalyzer::new();
+    sema.collect_signatures(funcs);
+    for func in funcs {
+        let warnings = sema
+            .analyze_function(func)
+            .map_err(|e| CompileError::Semantic(SemanticError { msg: format!("in '{}': {}", func.name, e), ..e }))?;
+        all_warnings.extend(warnings.into_iter().map(|w| (func.name.clone(), w)));
+    }
+
+    //the program starts running at main
+    if !funcs.iter().any(|f| f.name == "main") {
+        return Err(CompileError::Semantic("no 'main' function found".to_string().into()));
+    }
+    Ok(all_warnings)
+}
+
+//unoptimized IR for every function, in the order they were written
+pub fn generate_ir(funcs: &[Function]) -> Vec<IRFunction> {
+    funcs
+        .iter()
+        .map(|func| {
+            let mut generator = IRGenerator::new();
+            generator.emit_function(func);
+            IRFunction {
+                name: func.name.clone(),
+                params: func.params.clone(),
+                return_type: func.return_type.clone().unwrap_or(Type::Unit),
+                code: generator.finish(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the error compiling this source stops with
+    fn compile_error(source: &str) -> CompileError {
+        compile(source).expect_err("source should fail to compile")
+    }
+
+    #[test]
+    fn report_error_puts_caret_under_column() {
+        let rendered = report_error("func main() {\n    var x = 1 @ 2;\n}", 2, 15, "Lex error: unexpected character '@'");
+        assert_eq!(rendered, "Lex error: unexpected character '@'\n  |\n2 |     var x = 1 @ 2;\n  |               ^");
+    }
+
+    #[test]
+    fn parse_error_carries_its_position() {
+        let err = compile_error("func main() -> Int {\n    return 1\n}");
+        assert!(matches!(err, CompileError::Parse(_)), "{}", err);
+        assert_eq!(err.position(), Some((3, 1)));
+    }
+
+    #[test]
+    fn semantic_error_carries_statement_position() {
+        let err = compile_error("func main() -> Int {\n    var x = 1;\n    return y;\n}");
+        assert!(matches!(err, CompileError::Semantic(_)), "{}", err);
+        assert_eq!(err.position(), Some((3, 5)));
+    }
+
+    // what main returned
+    fn run(source: &str) -> VMValue {
+        compile(source).unwrap().expect("main should return a value")
+    }
+
+    #[test]
+    fn unary_minus_on_a_group() {
+        assert_eq!(run("func main() -> Int { return -(3 + 4); }"), VMValue::Int(-7));
+        assert_eq!(run("func f(b: Bool) -> Bool { return !b; } func main() -> Bool { return f(nah); }"), VMValue::Bool(true));
+    }
+
+    #[test]
+    fn variable_named_like_a_temp_is_kept() {
+        assert_eq!(run("func main() -> Int { var t2 = 10; var x = t2 + 1 + 2; return x; }"), VMValue::Int(13));
+    }
+
+    #[test]
+    fn comparisons_yield_bools() {
+        assert_eq!(run("func main() -> Bool { return 3 < 5; }"), VMValue::Bool(true));
+        assert_eq!(run("func f(a: Int) -> Bool { return a >= 5; } func main() -> Bool { return f(3); }"), VMValue::Bool(false));
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        // z is 0, so the right sides would fail with a division by zero if they ran
+        let and = "func f(z: Int, b: Bool) -> Bool { return b && 10 / z == 1; } func main() -> Bool { return f(0, nah); }";
+        assert_eq!(run(and), VMValue::Bool(false));
+        let or = "func f(z: Int, b: Bool) -> Bool { return b || 10 / z == 1; } func main() -> Bool { return f(0, yeah); }";
+        assert_eq!(run(or), VMValue::Bool(true));
+        let taken = "func f(z: Int, b: Bool) -> Bool { return b && 10 / z == 1; } func main() -> Bool { return f(0, yeah); }";
+        assert!(matches!(compile(taken), Err(CompileError::Runtime(VMError::DivisionByZero))));
+    }
+
+    #[test]
+    fn modulo_at_compile_time_and_runtime() {
+        assert_eq!(run("func main() -> Int { return 7 % 3; }"), VMValue::Int(1));
+        assert_eq!(run("func f(a: Int) -> Int { return a % 3; } func main() -> Int { return f(-7); }"), VMValue::Int(-1));
+    }
+
+    #[test]
+    fn char_literals_run() {
+        assert_eq!(run("func main() -> Char { return 'z'; }"), VMValue::Char('z'));
+        assert_eq!(run("func main() -> Char { var c = '\\t'; return c; }"), VMValue::Char('\t'));
+        assert!(matches!(compile("func main() -> Char { return 'ab'; }"), Err(CompileError::Lex(LexError::InvalidChar { .. }))));
+    }
+
+    #[test]
+    fn array_element_is_returned() {
+        assert_eq!(run("func main() -> Int { var a = [1, 2, 3]; return a[1]; }"), VMValue::Int(2));
+        let out_of_range = "func f(i: Int) -> Int { var a = [1, 2, 3]; return a[i]; } func main() -> Int { return f(3); }";
+        assert!(matches!(compile(out_of_range), Err(CompileError::Runtime(VMError::IndexOutOfBounds { index: 3, len: 3 }))));
+    }
+
+    #[test]
+    fn repl_expression_evaluates() {
+        assert_eq!(eval_line("40 + 2").unwrap(), Some(VMValue::Int(42)));
+        assert_eq!(eval_line("var x = 1;").unwrap(), None);
+    }
+
+    #[test]
+    fn strings_order_alphabetically() {
+        assert_eq!(run("func main() -> Bool { return \"a\" < \"b\"; }"), VMValue::Bool(true));
+        let at_runtime = "func f(a: Str, b: Str) -> Bool { return a > b; } func main() -> Bool { return f(\"apple\", \"banana\"); }";
+        assert_eq!(run(at_runtime), VMValue::Bool(false));
+    }
+
+    #[test]
+    fn int_is_promoted_in_mixed_arithmetic() {
+        assert_eq!(run("func main() -> Float { return 3 + 2.5; }"), VMValue::Float(5.5));
+        assert_eq!(run("func f(a: Int) -> Float { return a + 2.5; } func main() -> Float { return f(3); }"), VMValue::Float(5.5));
+        assert_eq!(run("func main() -> Int { return 3 + 2; }"), VMValue::Int(5));
+    }
+
+    #[test]
+    fn block_scoped_variable_shadows_then_ends() {
+        assert_eq!(run("func main() -> Int { var x = 1; { var x = 5; } return x; }"), VMValue::Int(1));
+    }
+
+    #[test]
+    fn break_and_continue_in_a_loop() {
+        let source = "func main() -> Int {
+            var i = 0;
+            var sum = 0;
+            while (i < 10) {
+                i = i + 1;
+                if (i == 3) { continue; }
+                if (i == 6) { break; }
+                sum = sum + i;
+            }
+            return sum;
+        }";
+        assert_eq!(run(source), VMValue::Int(1 + 2 + 4 + 5));
+    }
+
+    #[test]
+    fn string_repeat_folded_and_at_runtime() {
+        assert_eq!(run("func main() -> Str { return \"ab\" * 3; }"), VMValue::Str("ababab".to_string()));
+        let repeat = "func f(n: Int) -> Str { return \"ab\" * n; }";
+        assert_eq!(run(&format!("{} func main() -> Str {{ return f(2); }}", repeat)), VMValue::Str("abab".to_string()));
+        let negative = format!("{} func main() -> Str {{ return f(-1); }}", repeat);
+        assert!(matches!(compile(&negative), Err(CompileError::Runtime(VMError::BadRepeatCount(-1)))));
+    }
+}
@@ -1,73 +1,375 @@
-use crate::lex_layer::Token;
+use std::io::Write;
 
-mod lex_layer;
-mod file_translate;
-mod syntax_analyzer;
-mod semantic_analyzer;
-mod intermediate_code_generator;
-mod optimizer;
-mod target_code_generator;
+use compiler::intermediate_code_generator::format_ir;
+use compiler::lex_layer::SpannedToken;
+use compiler::target_code_generator::{VMError, VMProgram, VMValue};
+use compiler::CompileError;
+use compiler::{ast_json, c_backend, file_translate, lex_layer, optimizer, register_vm, target_code_generator};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    //allows to use enums from lexer
-    use lex_layer::LiteralType::*;
+    //the file to compile is myfile.txt when none is given and - for stdin,
+    //--emit=<stage> stops after that stage and prints its output (--emit=c prints the program as C,
+    //--emit=ast-json the parsed tree as JSON),
+    //-O0/-O1/-O2 picks the optimizations,
+    //-o <file> is where --emit=bytecode writes to and --run <file> runs a saved bytecode file,
+    //--max-steps <n> stops a run that executes more than n instructions,
+    //--repl reads lines from stdin and runs each one as it comes,
+    //--watch compiles again every time the file is saved until Ctrl-C,
+    //--vm=reg runs on the register VM instead of the stack VM
+    let mut path = "myfile.txt".to_string();
+    let mut emit = Emit::Run;
+    let mut opt_level = 2;
+    let mut out_path = None;
+    let mut run_path = None;
+    let mut max_steps = None;
+    let mut repl_mode = false;
+    let mut watch = false;
+    let mut backend = Backend::Stack;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "-o" || arg == "--run" {
+            let Some(file) = args.next() else {
+                eprintln!("{} needs a file name after it", arg);
+                std::process::exit(1);
+            };
+            if arg == "-o" {
+                out_path = Some(file);
+            } else {
+                run_path = Some(file);
+            }
+        } else if arg == "--repl" {
+            repl_mode = true;
+        } else if arg == "--watch" {
+            watch = true;
+        } else if let Some(vm) = arg.strip_prefix("--vm=") {
+            backend = match vm {
+                "stack" => Backend::Stack,
+                "reg" => Backend::Register,
+                _ => {
+                    eprintln!("Unknown --vm '{}', expected stack or reg", vm);
+                    std::process::exit(1);
+                }
+            };
+        } else if arg == "--max-steps" {
+            max_steps = match args.next().and_then(|n| n.parse::<usize>().ok()) {
+                Some(n) => Some(n),
+                None => {
+                    eprintln!("--max-steps needs a number after it");
+                    std::process::exit(1);
+                }
+            };
+        } else if let Some(level) = arg.strip_prefix("-O") {
+            opt_level = match level {
+                "0" => 0,
+                "1" => 1,
+                "2" => 2,
+                _ => {
+                    eprintln!("Unknown optimization level '{}', expected -O0, -O1 or -O2", arg);
+                    std::process::exit(1);
+                }
+            };
+        } else if let Some(stage) = arg.strip_prefix("--emit=") {
+            emit = match Emit::parse(stage) {
+                Some(emit) => emit,
+                None => {
+                    eprintln!("Unknown --emit stage '{}', expected tokens, ast, ast-json, ir, opt-ir, c, vm, bytecode or run", stage);
+                    std::process::exit(1);
+                }
+            };
+        } else {
+            path = arg;
+        }
+    }
+    if backend == Backend::Register && (repl_mode || run_path.is_some() || emit == Emit::Bytecode) {
+        eprintln!("--vm=reg can't be used with --repl, --run or --emit=bytecode, those only exist for the stack VM");
+        std::process::exit(1);
+    }
+    if repl_mode {
+        repl(max_steps);
+        return Ok(());
+    }
+    if let Some(run_path) = run_path {
+        run_bytecode(&run_path, max_steps);
+        return Ok(());
+    }
+    if emit == Emit::Bytecode && out_path.is_none() {
+        eprintln!("--emit=bytecode needs -o <file> to write to");
+        std::process::exit(1);
+    }
+    if watch && path == "-" {
+        eprintln!("--watch needs a file to watch, not stdin");
+        std::process::exit(1);
+    }
 
+    //the first modification time is read before compiling so a save during it still counts
+    let mut last_modified = file_translate::modified_time(&path);
+    let compiled = compile_path(&path, emit, backend, opt_level, out_path.as_deref(), max_steps);
+    if !watch {
+        if !compiled {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    //Ctrl-C's default handling ends the process, there's nothing to clean up
+    loop {
+        last_modified = file_translate::wait_for_change(&path, last_modified);
+        println!("\n[{} changed, compiling again]", path);
+        compile_path(&path, emit, backend, opt_level, out_path.as_deref(), max_steps);
+    }
+}
+
+//reads and compiles one program through the emit stage, false if any stage reported an error
+fn compile_path(path: &str, emit: Emit, backend: Backend, opt_level: u8, out_path: Option<&str>, max_steps: Option<usize>) -> bool {
     //creates tokens from lexer to use for syntax analyzer
     let mut buffer = std::string::String::new();
-    let contents = file_translate::read_file(&mut buffer)?;
-    let tokens = lex_layer::tokenize::<std::io::Error>(Ok(contents))?;
+    let read = if path == "-" {
+        file_translate::read_stdin(&mut buffer)
+    } else {
+        file_translate::read_file(path, &mut buffer)
+    };
+    let contents = match read {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("Could not read '{}': {}", path, e);
+            return false;
+        }
+    };
+    let tokens = match lex_layer::tokenize(contents) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            print_error(contents, &e.into());
+            return false;
+        }
+    };
 
-    println!("{:?}", tokens);
+    //one token per line after where it starts, whitespace is left out
+    if emit == Emit::Tokens {
+        for tok in &tokens {
+            if !matches!(tok.token, lex_layer::Token::Whitespace | lex_layer::Token::Eof) {
+                println!("{}:{}\t{}", tok.line, tok.col, tok.token);
+            }
+        }
+        return true;
+    }
 
+    check_sem_syn_ic(contents, tokens, emit, backend, opt_level, out_path, max_steps)
+}
 
-    check_sem_syn_ic(tokens);
+//the error with the line of source it points at, when it knows where that is
+fn print_error(source: &str, e: &CompileError) {
+    match e.position() {
+        Some((line, col)) => eprintln!("{}", compiler::report_error(source, line, col, &e.to_string())),
+        None => eprintln!("{}", e),
+    }
+}
 
+//the pipeline stage whose output gets printed, compiling stops after it
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Emit {
+    Tokens,
+    Ast,
+    AstJson,
+    Ir,
+    OptIr,
+    C,
+    Vm,
+    Bytecode,
+    Run,
+}
 
-    Ok(())
+//which VM lowers and runs the program
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    Stack,
+    Register,
 }
 
-fn check_sem_syn_ic(tokens: Vec<Token>) {
-    let mut parser = syntax_analyzer::Parser::new(&tokens);
-    match parser.parse_function() {
-        Ok(func) => {
-            println!("AST: {:#?}", func);
+impl Emit {
+    fn parse(stage: &str) -> Option<Emit> {
+        match stage {
+            "tokens" => Some(Emit::Tokens),
+            "ast" => Some(Emit::Ast),
+            "ast-json" => Some(Emit::AstJson),
+            "ir" => Some(Emit::Ir),
+            "opt-ir" => Some(Emit::OptIr),
+            "c" => Some(Emit::C),
+            "vm" => Some(Emit::Vm),
+            "bytecode" => Some(Emit::Bytecode),
+            "run" => Some(Emit::Run),
+            _ => None,
+        }
+    }
+}
+
+//parses, checks, lowers and runs the tokens through the emit stage, false if any of them failed
+fn check_sem_syn_ic(source: &str, tokens: Vec<SpannedToken>, emit: Emit, backend: Backend, opt_level: u8, out_path: Option<&str>, max_steps: Option<usize>) -> bool {
+    let funcs = match compiler::parse(&tokens) {
+        Ok(funcs) => funcs,
+        Err(e) => {
+            print_error(source, &e);
+            return false;
+        }
+    };
+    if emit == Emit::Ast {
+        println!("AST: {:#?}", funcs);
+        return true;
+    }
+    if emit == Emit::AstJson {
+        println!("{}", ast_json::functions_to_json(&funcs));
+        return true;
+    }
 
-            let mut sema = semantic_analyzer::SemanticAnalyzer::new();
-            match sema.analyze_function(&func) {
-                Ok(_) => {
-                    println!("Semantic analysis passed");
+    match compiler::analyze(&funcs) {
+        Ok(warnings) => {
+            for (func, warning) in warnings {
+                eprintln!("Warning in '{}': {}", func, warning);
+            }
+        }
+        Err(e) => {
+            print_error(source, &e);
+            return false;
+        }
+    }
 
-                    let mut irgen = intermediate_code_generator::IRGenerator::new();
-                    let ir = irgen.generate_function(&func);
-                    println!("Intermediate Code:\n{:#?}", ir);
+    let mut ir_funcs = compiler::generate_ir(&funcs);
+    if emit == Emit::Ir {
+        for func in &ir_funcs {
+            print!("Intermediate Code for '{}':\n{}", func.name, format_ir(&func.code));
+        }
+        return true;
+    }
+    let opt = optimizer::Optimizer::level(opt_level);
+    for func in &mut ir_funcs {
+        opt.run_function(func);
+    }
+    if emit == Emit::OptIr {
+        for func in &ir_funcs {
+            print!("Optimized IR for '{}':\n{}", func.name, format_ir(&func.code));
+        }
+        return true;
+    }
+    if emit == Emit::C {
+        print!("{}", c_backend::emit_c_program(&ir_funcs));
+        return true;
+    }
 
-                    let optimized = optimizer::optimize_ir(ir.clone());
+    if backend == Backend::Register {
+        return match register_vm::lower_program_to_registers(&ir_funcs, "main") {
+            Ok(reg_prog) if emit == Emit::Vm => {
+                print!("Register VM instrs:\n{}", register_vm::disassemble_registers(&reg_prog));
+                true
+            }
+            Ok(reg_prog) => {
+                let mut vm = register_vm::RegVM::new();
+                if let Some(max_steps) = max_steps {
+                    vm.set_max_steps(max_steps);
+                }
+                let run = vm.run(&reg_prog);
+                report_run(vm.output(), run)
+            }
+            Err(e) => {
+                eprintln!("Lowering error: {}", e);
+                false
+            }
+        };
+    }
 
-                    println!("Optimized IR:\n{:#?}", optimized);
+    // after IR generation:
+    match target_code_generator::lower_program(&ir_funcs, "main") {
+        Ok(vm_prog) => {
+            let vm_prog = if opt_level > 0 { target_code_generator::peephole(vm_prog) } else { vm_prog };
+            if emit == Emit::Vm {
+                print!("VM instrs:\n{}", target_code_generator::disassemble(&vm_prog));
+                return true;
+            }
+            if emit == Emit::Bytecode {
+                let out_path = out_path.expect("checked when parsing args");
+                if let Err(e) = std::fs::write(out_path, vm_prog.serialize()) {
+                    eprintln!("Could not write '{}': {}", out_path, e);
+                    return false;
+                }
+                return true;
+            }
 
-                    // after IR generation:
-                    let vm_prog = target_code_generator::lower_ir_to_vm(&ir);
-                    println!("VM instrs: {:#?}", vm_prog.instrs);
+            run_program(&vm_prog, max_steps)
+        }
+        Err(e) => {
+            eprintln!("Lowering error: {}", e);
+            false
+        }
+    }
+}
 
-                    let mut vm = target_code_generator::VM::new();
-                    let result = vm.run(&vm_prog);
-                    println!("Result: {:?}", result);
+//loads a file written by --emit=bytecode and runs it without going through the front end
+fn run_bytecode(path: &str, max_steps: Option<usize>) {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            eprintln!("Could not read '{}': {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    match VMProgram::deserialize(&bytes) {
+        Ok(vm_prog) => {
+            if !run_program(&vm_prog, max_steps) {
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("Bad bytecode in '{}': {}", path, e);
+            std::process::exit(1);
+        }
+    }
+}
 
-                }
-                Err(e) => eprintln!("Semantic error: {}", e),
+//each line is a bare expression to show the value of, or statements to run
+fn repl(max_steps: Option<usize>) {
+    let stdin = std::io::stdin();
+    let mut line = String::new();
+    loop {
+        print!("> ");
+        let _ = std::io::stdout().flush();
+        line.clear();
+        match stdin.read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+        match compiler::repl_program(&line) {
+            //a failing line is reported and the REPL carries on
+            Ok(vm_prog) => {
+                run_program(&vm_prog, max_steps);
             }
+            Err(e) => print_error(&line, &e),
         }
-        Err(e) => eprintln!("Parse error: {}", e),
     }
 }
 
-//this is for error checking by showing the tokens
-fn check_tokens() -> Result<(), std::io::Error> {
-    let mut buffer = String::new();
-    let contents = file_translate::read_file(&mut buffer)?;           // Result<&str, io::Error>
-    let tokens = lex_layer::tokenize::<std::io::Error>(Ok(contents))?; // tokenize consumes Result<&str, E>
+//false if the run stopped with a runtime error
+fn run_program(vm_prog: &VMProgram, max_steps: Option<usize>) -> bool {
+    let mut vm = target_code_generator::VM::new();
+    if let Some(max_steps) = max_steps {
+        vm.set_max_steps(max_steps);
+    }
+    let run = vm.run_to_end(vm_prog);
+    report_run(vm.output(), run)
+}
 
-    //prints the consumed tokens correctly based on file created
-    println!("Tokens: {:?}", tokens);
-    Ok(())
-}
\ No newline at end of file
+//what the program printed, then what main returned or the error that stopped it,
+//false for an error
+fn report_run(output: &[String], run: Result<Option<VMValue>, VMError>) -> bool {
+    for line in output {
+        println!("{}", line);
+    }
+    match run {
+        Ok(Some(result)) => println!("Result: {}", result),
+        Ok(None) => println!("Result: no value"),
+        Err(e) => {
+            eprintln!("Runtime error: {}", e);
+            return false;
+        }
+    }
+    true
+}
@@ -1,32 +1,178 @@
 use std::collections::{HashMap, HashSet};
 
-use crate::intermediate_code_generator::{IRInstr, IRValue};
+use crate::cfg::build_cfg;
+use crate::const_eval::{eval_binary, eval_unary};
+use crate::intermediate_code_generator::{is_temp_name, IRFunction, IRInstr, IRValue};
+use crate::semantic_analyzer::Type;
 
-/// Optimize a vector of IR instructions.
-/// Runs several passes until no more changes:
-///  - constant folding
-///  - constant / copy propagation
-///  - dead code elimination
-pub fn optimize_ir(mut code: Vec<IRInstr>) -> Vec<IRInstr> {
-    loop {
-        let before = code.len();
+// Safety net in case two passes keep undoing each other, real code settles
+// in a handful of rounds
+const MAX_PASSES: usize = 100;
 
-        // 1) Constant folding & propagation pass
-        code = constant_fold_and_propagate(&code);
+/// Which passes to run and how many rounds they get to settle.
+/// Build one with `Optimizer::new()...build()` or pick a preset with `Optimizer::level`.
+#[derive(Debug, Clone)]
+pub struct Optimizer {
+    constant_folding: bool,
+    algebraic: bool,
+    cse: bool,
+    copy_propagation: bool,
+    dce: bool,
+    unreachable: bool,
+    max_iterations: usize,
+}
 
-        // 2) Copy propagation pass (replace assigned temps/vars with their sources)
-        code = copy_propagation(&code);
+/// Every pass starts enabled, switch off the ones you don't want
+#[derive(Debug, Clone)]
+pub struct OptimizerBuilder {
+    config: Optimizer,
+}
 
-        // 3) Dead code elimination
-        code = dead_code_elimination(&code);
+impl OptimizerBuilder {
+    pub fn constant_folding(mut self, on: bool) -> Self {
+        self.config.constant_folding = on;
+        self
+    }
 
-        // stop when stable (no change in instruction count)
-        if code.len() == before {
-            break;
+    pub fn algebraic(mut self, on: bool) -> Self {
+        self.config.algebraic = on;
+        self
+    }
+
+    pub fn cse(mut self, on: bool) -> Self {
+        self.config.cse = on;
+        self
+    }
+
+    pub fn copy_propagation(mut self, on: bool) -> Self {
+        self.config.copy_propagation = on;
+        self
+    }
+
+    pub fn dce(mut self, on: bool) -> Self {
+        self.config.dce = on;
+        self
+    }
+
+    pub fn unreachable(mut self, on: bool) -> Self {
+        self.config.unreachable = on;
+        self
+    }
+
+    pub fn max_iterations(mut self, n: usize) -> Self {
+        self.config.max_iterations = n;
+        self
+    }
+
+    pub fn build(self) -> Optimizer {
+        self.config
+    }
+}
+
+impl Optimizer {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> OptimizerBuilder {
+        OptimizerBuilder {
+            config: Optimizer {
+                constant_folding: true,
+                algebraic: true,
+                cse: true,
+                copy_propagation: true,
+                dce: true,
+                unreachable: true,
+                max_iterations: MAX_PASSES,
+            },
         }
     }
 
-    code
+    /// Presets for -O0, -O1 and -O2:
+    ///  - 0 leaves the IR alone
+    ///  - 1 only folds constants and cleans up what that leaves dead
+    ///  - 2 (and anything higher) runs every pass
+    pub fn level(level: u8) -> Optimizer {
+        match level {
+            0 => Optimizer::new()
+                .constant_folding(false)
+                .algebraic(false)
+                .cse(false)
+                .copy_propagation(false)
+                .dce(false)
+                .unreachable(false)
+                .build(),
+            1 => Optimizer::new().algebraic(false).cse(false).copy_propagation(false).build(),
+            _ => Optimizer::new().build(),
+        }
+    }
+
+    /// Runs the enabled passes until a whole round leaves the code unchanged
+    pub fn run(&self, code: Vec<IRInstr>) -> Vec<IRInstr> {
+        self.run_with_params(code, &[])
+    }
+
+    /// Like `run` on a function's body, but the passes also know the types of
+    /// its parameters
+    pub fn run_function(&self, func: &mut IRFunction) {
+        func.code = self.run_with_params(std::mem::take(&mut func.code), &func.params);
+    }
+
+    fn run_with_params(&self, mut code: Vec<IRInstr>, params: &[(String, Type)]) -> Vec<IRInstr> {
+        for _ in 0..self.max_iterations {
+            let before = code.clone();
+
+            // 1) Constant folding & propagation pass
+            if self.constant_folding {
+                code = constant_fold_and_propagate(code);
+                code = remove_repeated_const_stores(code);
+            }
+
+            // 2) Algebraic identities (x + 0, x * 1, x * 0, ...)
+            if self.algebraic {
+                code = algebraic_simplify(code);
+            }
+
+            // 3) Reuse results of identical earlier computations
+            if self.cse {
+                code = common_subexpression_elimination(code);
+            }
+
+            // 4) Copy propagation pass (replace assigned temps/vars with their sources)
+            if self.copy_propagation {
+                code = copy_propagation(code);
+                code = remove_self_copies(code);
+            }
+
+            // 5) Dead code elimination
+            if self.dce {
+                code = dead_code_elimination(code, params);
+            }
+
+            // 6) Drop blocks that can't be reached from the entry, then the
+            //    jumps and labels pruned branches left behind
+            if self.unreachable {
+                code = unreachable_code_elimination(code);
+                code = remove_redundant_jumps(code);
+            }
+
+            // stop when stable (a whole round left every instruction as it was)
+            if code == before {
+                break;
+            }
+        }
+
+        code
+    }
+}
+
+/// Optimize a vector of IR instructions.
+/// Runs several passes until no more changes:
+///  - constant folding (and dropping stores of a constant already there)
+///  - algebraic identity simplification
+///  - common subexpression elimination
+///  - constant / copy propagation
+///  - dead code elimination
+///  - unreachable code elimination
+pub fn optimize_ir(code: Vec<IRInstr>) -> Vec<IRInstr> {
+    Optimizer::new().build().run(code)
 }
 
 // -----------------------------
@@ -35,12 +181,15 @@ pub fn optimize_ir(mut code: Vec<IRInstr>) -> Vec<IRInstr> {
 //
 // Walks instructions in order and attempts to evaluate BinaryOp when operands are
 // known constants (either literal or previously folded temps). It also tracks
-// simple constant assignments (e.g., t1 = 5 or x = t1 where t1 is a constant).
-fn constant_fold_and_propagate(code: &[IRInstr]) -> Vec<IRInstr> {
+// simple constant assignments (e.g., %1 = 5 or x = %1 where %1 is a constant).
+fn constant_fold_and_propagate(code: Vec<IRInstr>) -> Vec<IRInstr> {
     let mut new_code = Vec::with_capacity(code.len());
     // map from name (var or temp string) to constant IRValue
     let mut consts: HashMap<String, IRValue> = HashMap::new();
 
+    // labels some jump goes to, the rest are only ever reached by falling into them
+    let targeted = jump_targets(&code);
+
     // helper: try to get a constant for a name (temp or var)
     let get_const = |name: &str, consts: &HashMap<String, IRValue>| -> Option<IRValue> {
         consts.get(name).cloned()
@@ -51,217 +200,247 @@ fn constant_fold_and_propagate(code: &[IRInstr]) -> Vec<IRInstr> {
             IRInstr::Assign(target, value) => {
                 // If value is literal, record it as constant.
                 // If value is a Temp or Var that maps to a constant, propagate.
-                let resolved_value = match value {
-                    IRValue::Int(_) | IRValue::Bool(_) | IRValue::Str(_) => Some(value.clone()),
+                let resolved_value = match &value {
+                    IRValue::Int(_) | IRValue::Float(_) | IRValue::Bool(_) | IRValue::Str(_) | IRValue::Char(_) => {
+                        Some(value.clone())
+                    }
                     IRValue::Temp(t) | IRValue::Var(t) => get_const(t, &consts),
                 };
 
                 if let Some(cv) = resolved_value {
                     // We can replace the assign with a direct constant assign
-                    new_code.push(IRInstr::Assign(target.clone(), cv.clone()));
-                    consts.insert(target.clone(), cv);
+                    consts.insert(target.clone(), cv.clone());
+                    new_code.push(IRInstr::Assign(target, cv));
                 } else {
                     // Not a known constant; keep the original assign and remove any const entry
-                    new_code.push(IRInstr::Assign(target.clone(), value.clone()));
-                    consts.remove(target);
+                    consts.remove(&target);
+                    new_code.push(IRInstr::Assign(target, value));
                 }
             }
 
             IRInstr::BinaryOp(result, left, op, right) => {
                 // Try to resolve left/right into constants (either var/temp or literal already recorded)
-                let left_const = get_const(left, &consts);
-                let right_const = get_const(right, &consts);
-
-                match (&left_const, &right_const) {
-                    (Some(IRValue::Int(a)), Some(IRValue::Int(b))) => {
-                        // integer arithmetic folding
-                        let folded = match op.as_str() {
-                            "+" => IRValue::Int(a + b),
-                            "-" => IRValue::Int(a - b),
-                            "*" => IRValue::Int(a * b),
-                            "/" => {
-                                if *b == 0 {
-                                    new_code.push(IRInstr::BinaryOp(
-                                        result.clone(),
-                                        left.clone(),
-                                        op.clone(),
-                                        right.clone(),
-                                    ));
-                                    continue;
-                                } else {
-                                    IRValue::Int(a / b)
-                                }
-                            }
-                            _ => {
-                                new_code.push(IRInstr::BinaryOp(
-                                    result.clone(),
-                                    left.clone(),
-                                    op.clone(),
-                                    right.clone(),
-                                ));
-                                continue;
-                            }
-                        };
-                        new_code.push(IRInstr::Assign(result.clone(), folded.clone()));
-                        consts.insert(result.clone(), folded);
-                    }
+                let left_const = get_const(&left, &consts);
+                let right_const = get_const(&right, &consts);
 
-                    (Some(IRValue::Str(a)), Some(IRValue::Str(b))) if op == "+" => {
-                        let folded = IRValue::Str(format!("{}{}", a, b));
-                        new_code.push(IRInstr::Assign(result.clone(), folded.clone()));
-                        consts.insert(result.clone(), folded);
-                    }
+                let folded = match (&left_const, &right_const) {
+                    (Some(a), Some(b)) => fold_binary(&op, a, b),
+                    _ => None,
+                };
 
-                    (Some(_), None) | (None, Some(_)) | (None, None) => {
-                        new_code.push(IRInstr::BinaryOp(
-                            result.clone(),
-                            left.clone(),
-                            op.clone(),
-                            right.clone(),
-                        ));
-                        consts.remove(result);
+                match folded {
+                    Some(folded) => {
+                        consts.insert(result.clone(), folded.clone());
+                        new_code.push(IRInstr::Assign(result, folded));
                     }
 
-                    //catch-all for Bool, Temp, Var, etc.
-                    _ => {
-                        new_code.push(IRInstr::BinaryOp(
-                            result.clone(),
-                            left.clone(),
-                            op.clone(),
-                            right.clone(),
-                        ));
-                        consts.remove(result);
+                    // Can't fold: one side isn't a known constant (we could store
+                    // literals into temps earlier, but leave that for other passes),
+                    // or the op isn't defined for these constants.
+                    None => {
+                        // It's not a constant result
+                        consts.remove(&result);
+                        new_code.push(IRInstr::BinaryOp(result, left, op, right));
                     }
+                }
+            }
 
-                    (Some(IRValue::Str(a)), Some(IRValue::Str(b))) if op == "+" => {
-                        // string concatenation folding
-                        let folded = IRValue::Str(format!("{}{}", a, b));
-                        new_code.push(IRInstr::Assign(result.clone(), folded.clone()));
-                        consts.insert(result.clone(), folded);
-                    }
+            IRInstr::Label(label) => {
+                // Control can reach a jump target from more than one place, so nothing
+                // learned about constants before it is safe to keep.
+                if targeted.contains(&label) {
+                    consts.clear();
+                }
+                new_code.push(IRInstr::Label(label));
+            }
 
-                    (Some(lc), None) | (None, Some(lc)) => {
-                        // One side constant, other not. Can't fold fully, but we can push a BinaryOp
-                        // If left or right are literals, we could store them into temps earlier, but
-                        // leave for other passes.
-                        new_code.push(IRInstr::BinaryOp(
-                            result.clone(),
-                            left.clone(),
-                            op.clone(),
-                            right.clone(),
-                        ));
-                        // It's not a constant result
-                        consts.remove(result);
-                    }
+            // a known condition decides the branch now: false always jumps,
+            // true never does
+            IRInstr::JumpIfFalse(cond, label) => match get_const(&cond, &consts) {
+                Some(IRValue::Bool(false)) => new_code.push(IRInstr::Jump(label)),
+                Some(IRValue::Bool(true)) => {}
+                _ => new_code.push(IRInstr::JumpIfFalse(cond, label)),
+            },
 
-                    (None, None) => {
-                        // no folding possible
-                        new_code.push(IRInstr::BinaryOp(
-                            result.clone(),
-                            left.clone(),
-                            op.clone(),
-                            right.clone(),
-                        ));
-                        consts.remove(result);
+            IRInstr::UnaryOp(result, op, operand) => {
+                match get_const(&operand, &consts).and_then(|v| fold_unary(&op, &v)) {
+                    Some(folded) => {
+                        consts.insert(result.clone(), folded.clone());
+                        new_code.push(IRInstr::Assign(result, folded));
+                    }
+                    None => {
+                        consts.remove(&result);
+                        new_code.push(IRInstr::UnaryOp(result, op, operand));
                     }
                 }
             }
 
-            IRInstr::Return(name) => {
-                // If the returned name maps to a constant, replace return with that constant assigned to a temp
-                // or leave as-is if you prefer. Simpler: keep Return(name) unchanged, but we could fold.
-                if let Some(cv) = consts.get(name) {
-                    // turn into Assign(temp, const); Return(temp)
-                    let tmp = format!("t_fold_return_{}", name);
-                    new_code.push(IRInstr::Assign(tmp.clone(), cv.clone()));
-                    new_code.push(IRInstr::Return(tmp));
-                } else {
-                    new_code.push(IRInstr::Return(name.clone()));
-                }
+            IRInstr::Call(ref result, _, _)
+            | IRInstr::MakeArray(ref result, _)
+            | IRInstr::Index(ref result, _, _)
+            | IRInstr::Phi(ref result, _) => {
+                // the callee's result is only known at runtime, arrays aren't tracked as constants
+                // and a phi's value depends on the path taken
+                consts.remove(result);
+                new_code.push(instr);
             }
+
+            // Keep Return(name) unchanged: if name is a known constant the assign
+            // feeding it was already folded above. Wrapping it in a fresh temp here
+            // would grow the code every iteration and the fixpoint would never settle.
+            IRInstr::Return(_) | IRInstr::Jump(_) => new_code.push(instr),
         }
     }
 
     new_code
 }
 
+// Evaluates `op a` for a constant, None when it doesn't apply or -i64::MIN overflows
+fn fold_unary(op: &str, a: &IRValue) -> Option<IRValue> {
+    eval_unary(op, a.constant()?).and_then(IRValue::immediate)
+}
+
+// Evaluates `a op b` for two constants with the same folder the IR generator uses.
+// Returns None when the op isn't defined for these operand types or can't be done
+// at compile time (division by zero, i64 overflow, a huge string repeat), in which
+// case the BinaryOp is left for the VM.
+fn fold_binary(op: &str, a: &IRValue, b: &IRValue) -> Option<IRValue> {
+    eval_binary(op, a.constant()?, b.constant()?).and_then(IRValue::immediate)
+}
+
 // -----------------------------
-// Pass: Copy propagation
+// Pass: Repeated constant store removal
 // -----------------------------
 //
-// Replace uses of variables/temps that are simple copies of other temps/vars.
-// e.g. Assign("d", Temp("t1")) followed by uses of "d" -> replace with "t1".
-fn copy_propagation(code: &[IRInstr]) -> Vec<IRInstr> {
-    // Build a map of direct copies: name -> source_name
-    let mut copy_map: HashMap<String, String> = HashMap::new();
+// Folding can leave `x = 5` when x is already known to hold 5, e.g. the same
+// literal stored twice. The second store changes nothing, so it goes. Known
+// values are tracked the same way as in folding and forgotten at jump targets.
+fn remove_repeated_const_stores(code: Vec<IRInstr>) -> Vec<IRInstr> {
+    let targeted = jump_targets(&code);
+    let mut consts: HashMap<String, IRValue> = HashMap::new();
+    let mut new_code = Vec::with_capacity(code.len());
 
-    // First pass: collect direct copy assignments: Assign(a, Temp(t)) or Assign(a, Var(t)) where t is not a literal
     for instr in code {
-        if let IRInstr::Assign(target, value) = instr {
-            match value {
-                IRValue::Temp(src) | IRValue::Var(src) => {
-                    // record copy target -> src (overwrite previous if any)
-                    copy_map.insert(target.clone(), src.clone());
+        match &instr {
+            IRInstr::Assign(target, value) if is_literal(value) => {
+                if consts.get(target).is_some_and(|known| same_constant(known, value)) {
+                    continue;
                 }
-                _ => {
-                    // assignment of literal or non-copy; remove any previous mapping
-                    copy_map.remove(target);
+                consts.insert(target.clone(), value.clone());
+            }
+            IRInstr::Label(label) if targeted.contains(label) => consts.clear(),
+            _ => {
+                if let Some(def) = instr.defs_and_uses().0 {
+                    consts.remove(def);
                 }
             }
-        } else {
-            // other instr: no target mapping change here
         }
+        new_code.push(instr);
     }
 
-    // Second pass: rewrite instructions replacing targets that map to copies transitively.
-    // We must resolve transitively (a -> b, b -> c => a -> c)
-    let resolve_copy = |mut name: String, map: &HashMap<String, String>| -> String {
-        let mut seen = HashSet::new();
-        while let Some(next) = map.get(&name) {
-            if !seen.insert(name.clone()) {
-                break; // cycle; stop
-            }
-            name = next.clone();
-        }
-        name
-    };
+    new_code
+}
+
+fn is_literal(value: &IRValue) -> bool {
+    !matches!(value, IRValue::Var(_) | IRValue::Temp(_))
+}
 
+// like ==, but floats compare by bits so 0.0 and -0.0 stay different
+fn same_constant(a: &IRValue, b: &IRValue) -> bool {
+    match (a, b) {
+        (IRValue::Float(x), IRValue::Float(y)) => x.to_bits() == y.to_bits(),
+        _ => a == b,
+    }
+}
+
+// -----------------------------
+// Pass: Algebraic simplification
+// -----------------------------
+//
+// Rewrites BinaryOps where one side is a known integer constant that makes the
+// operation trivial, even if the other side isn't constant:
+//   x + 0, 0 + x, x - 0, x * 1, 1 * x, x / 1  ->  result = x
+//   x * 0, 0 * x                             ->  result = 0, only when x is an Int
+// For a Float x the product has to be 0.0 (or NaN), and for a string it's "",
+// so x * 0 waits until x is known to hold an Int. The leftover constant temps
+// are cleaned up by DCE.
+fn algebraic_simplify(code: Vec<IRInstr>) -> Vec<IRInstr> {
     let mut new_code = Vec::with_capacity(code.len());
+    // integer constants currently held by a name
+    let mut consts: HashMap<String, i64> = HashMap::new();
+    // names known to hold an Int. A name has the same type on every path, so
+    // unlike consts this isn't forgotten at labels
+    let mut ints: HashSet<String> = HashSet::new();
+
     for instr in code {
         match instr {
-            IRInstr::Assign(target, value) => {
-                // If value is a name and that name maps to something, resolve it.
-                let new_val = match value {
-                    IRValue::Temp(t) | IRValue::Var(t) => {
-                        let resolved = resolve_copy(t.clone(), &copy_map);
-                        // If resolved equals target, keep as original to avoid self-copy.
-                        if &resolved == target {
-                            value.clone()
-                        } else {
-                            // produce Var(resolved) — keep using Var/Temp indistinctly in IRValue
-                            IRValue::Var(resolved)
-                        }
+            IRInstr::Assign(ref target, ref value) => {
+                if let IRValue::Int(n) = value {
+                    consts.insert(target.clone(), *n);
+                } else {
+                    consts.remove(target);
+                }
+                let is_int = match value {
+                    IRValue::Int(_) => true,
+                    IRValue::Var(v) | IRValue::Temp(v) => ints.contains(v),
+                    _ => false,
+                };
+                if is_int {
+                    ints.insert(target.clone());
+                }
+                new_code.push(instr);
+            }
+
+            IRInstr::BinaryOp(ref result, ref left, ref op, ref right) => {
+                let l = consts.get(left).copied();
+                let r = consts.get(right).copied();
+                let both_int = ints.contains(left) && ints.contains(right);
+
+                let simplified = match (op.as_str(), l, r) {
+                    ("*", _, Some(0)) | ("*", Some(0), _) if both_int => Some(IRValue::Int(0)),
+                    ("+", _, Some(0)) | ("-", _, Some(0)) | ("*", _, Some(1)) | ("/", _, Some(1)) => {
+                        Some(name_value(left))
                     }
-                    _ => value.clone(),
+                    ("+", Some(0), _) | ("*", Some(1), _) => Some(name_value(right)),
+                    _ => None,
                 };
-                new_code.push(IRInstr::Assign(target.clone(), new_val));
+
+                consts.remove(result);
+                if let Some(IRValue::Int(n)) = simplified {
+                    consts.insert(result.clone(), n);
+                }
+                if both_int && matches!(op.as_str(), "+" | "-" | "*" | "/" | "%") {
+                    ints.insert(result.clone());
+                }
+                match simplified {
+                    Some(value) => new_code.push(IRInstr::Assign(result.clone(), value)),
+                    None => new_code.push(instr),
+                }
+            }
+
+            IRInstr::Label(_) => {
+                // join point: constants from other paths may differ
+                consts.clear();
+                new_code.push(instr);
             }
 
-            IRInstr::BinaryOp(res, l, op, r) => {
-                let new_l = resolve_copy(l.clone(), &copy_map);
-                let new_r = resolve_copy(r.clone(), &copy_map);
-                new_code.push(IRInstr::BinaryOp(
-                    res.clone(),
-                    new_l,
-                    op.clone(),
-                    new_r,
-                ));
+            IRInstr::UnaryOp(ref result, ref op, ref operand) => {
+                consts.remove(result);
+                if op == "-" && ints.contains(operand) {
+                    ints.insert(result.clone());
+                }
+                new_code.push(instr);
             }
 
-            IRInstr::Return(name) => {
-                let new_name = resolve_copy(name.clone(), &copy_map);
-                new_code.push(IRInstr::Return(new_name));
+            IRInstr::Call(ref result, _, _)
+            | IRInstr::MakeArray(ref result, _)
+            | IRInstr::Index(ref result, _, _)
+            | IRInstr::Phi(ref result, _) => {
+                consts.remove(result);
+                new_code.push(instr);
             }
+
+            IRInstr::Return(_) | IRInstr::Jump(_) | IRInstr::JumpIfFalse(_, _) => new_code.push(instr),
         }
     }
 
@@ -269,70 +448,684 @@ fn copy_propagation(code: &[IRInstr]) -> Vec<IRInstr> {
 }
 
 // -----------------------------
-// Pass: Dead Code Elimination (DCE)
+// Pass: Common subexpression elimination
 // -----------------------------
 //
-// Remove assignments to temps/vars that are never used later. This is conservative:
-// - We don't remove assignments to names used by Return or used as left-hand of BinaryOp.
-// - We iterate until no more removals happen.
-fn dead_code_elimination(code: &[IRInstr]) -> Vec<IRInstr> {
-    let mut code_vec: Vec<IRInstr> = code.to_vec();
+// Remembers which name holds the result of each (left, op, right) seen so far.
+// A later BinaryOp with the same operands becomes a copy of that name. Any write
+// to a name forgets the computations that read it or whose result lived in it.
+fn common_subexpression_elimination(code: Vec<IRInstr>) -> Vec<IRInstr> {
+    let mut new_code = Vec::with_capacity(code.len());
+    let mut available: HashMap<(String, String, String), String> = HashMap::new();
 
-    loop {
-        // compute usage counts of names (temps/vars)
-        let mut uses: HashMap<String, usize> = HashMap::new();
-        for instr in &code_vec {
-            match instr {
-                IRInstr::Assign(_, value) => {
-                    match value {
-                        IRValue::Var(name) | IRValue::Temp(name) => {
-                            *uses.entry(name.clone()).or_default() += 1;
+    // name was just overwritten, so nothing recorded with it is valid anymore
+    let invalidate = |name: &String, available: &mut HashMap<(String, String, String), String>| {
+        available.retain(|(l, _, r), holder| l != name && r != name && holder != name);
+    };
+
+    for instr in code {
+        match instr {
+            IRInstr::BinaryOp(result, left, op, right) => {
+                let key = (left, op, right);
+                let earlier = available.get(&key).cloned();
+                invalidate(&result, &mut available);
+                match earlier {
+                    Some(holder) => {
+                        let value = name_value(&holder);
+                        new_code.push(IRInstr::Assign(result, value));
+                    }
+                    None => {
+                        let (left, op, right) = key;
+                        // t = t + 1 reads the old t, so there's nothing to reuse afterwards
+                        if result != left && result != right {
+                            available.insert((left.clone(), op.clone(), right.clone()), result.clone());
                         }
-                        _ => {}
+                        new_code.push(IRInstr::BinaryOp(result, left, op, right));
                     }
                 }
-                IRInstr::BinaryOp(_, l, _, r) => {
-                    *uses.entry(l.clone()).or_default() += 1;
-                    *uses.entry(r.clone()).or_default() += 1;
-                }
-                IRInstr::Return(name) => {
-                    *uses.entry(name.clone()).or_default() += 1;
-                }
+            }
+
+            IRInstr::Assign(ref target, _)
+            | IRInstr::UnaryOp(ref target, _, _)
+            | IRInstr::Call(ref target, _, _)
+            | IRInstr::MakeArray(ref target, _)
+            | IRInstr::Index(ref target, _, _)
+            | IRInstr::Phi(ref target, _) => {
+                invalidate(target, &mut available);
+                new_code.push(instr);
+            }
+
+            IRInstr::Label(_) => {
+                // join point: another path may have computed something else
+                available.clear();
+                new_code.push(instr);
+            }
+
+            IRInstr::Return(_) | IRInstr::Jump(_) | IRInstr::JumpIfFalse(_, _) => new_code.push(instr),
+        }
+    }
+
+    new_code
+}
+
+// -----------------------------
+// Pass: Copy propagation
+// -----------------------------
+//
+// Replace uses of variables/temps that are simple copies of other temps/vars.
+// e.g. Assign("d", Temp("%1")) followed by uses of "d" -> replace with "%1".
+fn copy_propagation(code: Vec<IRInstr>) -> Vec<IRInstr> {
+    // Count how many times each name is written. A copy is only safe to propagate
+    // when neither side is ever redefined, otherwise (especially across branches)
+    // a use could see a different value than the copy recorded.
+    let mut defs: HashMap<String, usize> = HashMap::new();
+    for instr in &code {
+        match instr {
+            IRInstr::Assign(target, _)
+            | IRInstr::BinaryOp(target, _, _, _)
+            | IRInstr::UnaryOp(target, _, _)
+            | IRInstr::Call(target, _, _)
+            | IRInstr::MakeArray(target, _)
+            | IRInstr::Index(target, _, _)
+            | IRInstr::Phi(target, _) => {
+                *defs.entry(target.clone()).or_default() += 1;
+            }
+            _ => {}
+        }
+    }
+    // A name read before anything writes it, like a parameter, already holds
+    // a value on entry, which counts as one more definition
+    for name in live_on_entry(&code) {
+        *defs.entry(name).or_default() += 1;
+    }
+
+    // Build a map of direct copies: name -> source, kept as Temp/Var like it was written
+    let mut copy_map: HashMap<String, IRValue> = HashMap::new();
+
+    // First pass: collect direct copy assignments: Assign(a, Temp(t)) or Assign(a, Var(t)) where t is not a literal
+    for instr in &code {
+        if let IRInstr::Assign(target, value @ (IRValue::Temp(src) | IRValue::Var(src))) = instr {
+            let single_def = |name: &String| defs.get(name).copied().unwrap_or(0) <= 1;
+            if single_def(target) && single_def(src) {
+                copy_map.insert(target.clone(), value.clone());
             }
         }
+    }
+
+    // Second pass: rewrite instructions replacing names that map to copies transitively.
+    // Only names that actually change get a new String, everything else is moved along.
+    let rename = |name: String| -> String {
+        match resolve_copy(&name, &copy_map) {
+            Some(IRValue::Temp(resolved) | IRValue::Var(resolved)) => resolved.clone(),
+            _ => name,
+        }
+    };
+
+    let mut new_code = Vec::with_capacity(code.len());
+    for instr in code {
+        let new_instr = match instr {
+            IRInstr::Assign(target, value) => {
+                // If value is a name and that name maps to something, resolve it,
+                // unless that would turn it into a self-copy.
+                let resolved = match &value {
+                    IRValue::Temp(src) | IRValue::Var(src) => match resolve_copy(src, &copy_map) {
+                        Some(IRValue::Temp(name) | IRValue::Var(name)) if name == &target => None,
+                        other => other.cloned(),
+                    },
+                    _ => None,
+                };
+                IRInstr::Assign(target, resolved.unwrap_or(value))
+            }
+            IRInstr::BinaryOp(res, l, op, r) => IRInstr::BinaryOp(res, rename(l), op, rename(r)),
+            IRInstr::UnaryOp(res, op, operand) => IRInstr::UnaryOp(res, op, rename(operand)),
+            IRInstr::Return(name) => IRInstr::Return(name.map(rename)),
+            IRInstr::JumpIfFalse(cond, label) => IRInstr::JumpIfFalse(rename(cond), label),
+            IRInstr::Call(res, name, args) => IRInstr::Call(res, name, args.into_iter().map(rename).collect()),
+            IRInstr::MakeArray(res, elements) => IRInstr::MakeArray(res, elements.into_iter().map(rename).collect()),
+            IRInstr::Index(res, base, index) => IRInstr::Index(res, rename(base), rename(index)),
+            IRInstr::Phi(res, sources) => IRInstr::Phi(res, sources.into_iter().map(rename).collect()),
+            IRInstr::Label(_) | IRInstr::Jump(_) => instr,
+        };
+        new_code.push(new_instr);
+    }
+
+    new_code
+}
+
+// Follows a -> b -> c through the copy map and gives the last source in the
+// chain, kept as whichever of Temp/Var it was written as. None when the name
+// isn't a copy at all.
+fn resolve_copy<'a>(name: &str, map: &'a HashMap<String, IRValue>) -> Option<&'a IRValue> {
+    let mut current = map.get(name)?;
+    let mut seen = HashSet::from([name]);
+    while let IRValue::Temp(next) | IRValue::Var(next) = current {
+        let Some(value) = map.get(next) else { break };
+        if !seen.insert(next.as_str()) {
+            break; // cycle; stop
+        }
+        current = value;
+    }
+    Some(current)
+}
 
-        // find instructions that write to a name that has zero uses
-        let mut removed_any = false;
-        let mut new_code = Vec::with_capacity(code_vec.len());
-        for instr in &code_vec {
+// -----------------------------
+// Pass: Self-copy removal
+// -----------------------------
+//
+// Copy propagation can leave behind a = a, which does nothing. DCE won't catch
+// it when a is a real var, so drop those here.
+fn remove_self_copies(mut code: Vec<IRInstr>) -> Vec<IRInstr> {
+    code.retain(|instr| {
+        !matches!(instr, IRInstr::Assign(target, IRValue::Var(src) | IRValue::Temp(src)) if src == target)
+    });
+    code
+}
+
+// -----------------------------
+// Pass: Dead Code Elimination (DCE)
+// -----------------------------
+//
+// Remove writes whose value is never read afterwards, on any path. Liveness
+// tells us which names can still be read after each instruction, so this
+// covers real vars and temps written more than once too. Calls stay since they
+// can print, and so does indexing since it can fail at runtime. A dead
+// BinaryOp only stays when it might fail: a / or % by something that isn't a
+// known non-zero Int, or a * that might be a string Repeat (negative count).
+fn dead_code_elimination(mut code: Vec<IRInstr>, params: &[(String, Type)]) -> Vec<IRInstr> {
+    loop {
+        let mut live_out = liveness(&code).into_iter();
+        let types = known_types(&code, params);
+        let consts = single_int_consts(&code);
+
+        let before = code.len();
+        code.retain(|instr| {
+            let live = live_out.next().expect("one live-out set per instruction");
             match instr {
-                IRInstr::Assign(target, _) => {
-                    let count = uses.get(target).copied().unwrap_or(0);
-                    if count == 0 && is_temporary_name(target) {
-                        // remove assignment to unused temporary
-                        removed_any = true;
-                        continue;
-                    } else {
-                        // keep assignment (either used or it's a non-temp var)
-                        new_code.push(instr.clone());
-                    }
+                IRInstr::Assign(target, _) => live.contains(target),
+                IRInstr::BinaryOp(target, left, op, right) => {
+                    live.contains(target) || binary_may_fail(left, op, right, &types, &consts)
                 }
-                _ => new_code.push(instr.clone()),
+                IRInstr::UnaryOp(target, _, _) | IRInstr::MakeArray(target, _) => live.contains(target),
+                _ => true,
             }
+        });
+
+        if code.len() == before {
+            break;
         }
+    }
 
-        code_vec = new_code;
+    code
+}
 
-        if !removed_any {
-            break;
+// Whether left op right can stop the program, going by what the lowering
+// turns it into: a * on a string left side is a Repeat, on an Int or Float a
+// multiplication that can't fail. Float division gives inf or NaN instead of
+// failing, Int / and % only fail on a zero divisor.
+fn binary_may_fail(
+    left: &str,
+    op: &str,
+    right: &str,
+    types: &HashMap<String, Type>,
+    consts: &HashMap<String, i64>,
+) -> bool {
+    let is_float = |name: &str| types.get(name) == Some(&Type::Float);
+    match op {
+        "*" => !matches!(types.get(left), Some(Type::Int | Type::Float)),
+        "/" if is_float(left) || is_float(right) => false,
+        "/" | "%" => consts.get(right).is_none_or(|&n| n == 0),
+        _ => false,
+    }
+}
+
+// The type of every name we can tell from the code: parameters, literals,
+// copies and arithmetic on names whose types are known. A name has the same
+// type on every path, so one walk in order is enough. Call results and
+// array elements stay unknown.
+fn known_types(code: &[IRInstr], params: &[(String, Type)]) -> HashMap<String, Type> {
+    let mut types: HashMap<String, Type> = params.iter().cloned().collect();
+    for instr in code {
+        let (target, ty) = match instr {
+            IRInstr::Assign(target, value) => {
+                let ty = match value {
+                    IRValue::Int(_) => Some(Type::Int),
+                    IRValue::Float(_) => Some(Type::Float),
+                    IRValue::Bool(_) => Some(Type::Bool),
+                    IRValue::Str(_) => Some(Type::Str),
+                    IRValue::Char(_) => Some(Type::Char),
+                    IRValue::Var(v) | IRValue::Temp(v) => types.get(v).cloned(),
+                };
+                (target, ty)
+            }
+            IRInstr::BinaryOp(target, left, op, right) => {
+                let ty = match (op.as_str(), types.get(left), types.get(right)) {
+                    ("==" | "!=" | "<" | "<=" | ">" | ">=", _, _) => Some(Type::Bool),
+                    (_, Some(Type::Float), Some(Type::Int | Type::Float))
+                    | (_, Some(Type::Int), Some(Type::Float)) => Some(Type::Float),
+                    (_, Some(Type::Int), Some(Type::Int)) => Some(Type::Int),
+                    (_, Some(Type::Str), Some(_)) => Some(Type::Str),
+                    _ => None,
+                };
+                (target, ty)
+            }
+            IRInstr::UnaryOp(target, op, operand) => {
+                let ty = match op.as_str() {
+                    "!" => Some(Type::Bool),
+                    _ => types.get(operand).cloned(),
+                };
+                (target, ty)
+            }
+            _ => continue,
+        };
+        if let Some(ty) = ty {
+            types.insert(target.clone(), ty);
+        }
+    }
+    types
+}
+
+// Names whose only definition is a single Int literal, so every read sees that
+// value. Anything written twice, or read before it is written, is left out.
+fn single_int_consts(code: &[IRInstr]) -> HashMap<String, i64> {
+    let mut defs: HashMap<String, usize> = HashMap::new();
+    for instr in code {
+        if let (Some(d), _) = instr.defs_and_uses() {
+            *defs.entry(d.clone()).or_default() += 1;
+        }
+    }
+    for name in live_on_entry(code) {
+        *defs.entry(name).or_default() += 1;
+    }
+    code.iter()
+        .filter_map(|instr| match instr {
+            IRInstr::Assign(target, IRValue::Int(n)) if defs.get(target) == Some(&1) => Some((target.clone(), *n)),
+            _ => None,
+        })
+        .collect()
+}
+
+// -----------------------------
+// Analysis: Liveness
+// -----------------------------
+//
+// For each instruction, the set of names that may still be read after it runs
+// (its live-out set). Works backwards from each instruction's successors and
+// repeats until nothing changes, since jumps can go backwards.
+fn liveness(code: &[IRInstr]) -> Vec<HashSet<String>> {
+    let labels: HashMap<&str, usize> = code
+        .iter()
+        .enumerate()
+        .filter_map(|(i, instr)| match instr {
+            IRInstr::Label(label) => Some((label.as_str(), i)),
+            _ => None,
+        })
+        .collect();
+
+    // where control can go after instruction i
+    let successors = |i: usize| -> Vec<usize> {
+        let next = if i + 1 < code.len() { vec![i + 1] } else { Vec::new() };
+        match &code[i] {
+            IRInstr::Return(_) => Vec::new(),
+            IRInstr::Jump(label) => labels.get(label.as_str()).copied().into_iter().collect(),
+            IRInstr::JumpIfFalse(_, label) => {
+                let mut succ = next;
+                succ.extend(labels.get(label.as_str()).copied());
+                succ
+            }
+            _ => next,
+        }
+    };
+
+    let mut live_in: Vec<HashSet<String>> = vec![HashSet::new(); code.len()];
+    let mut live_out: Vec<HashSet<String>> = vec![HashSet::new(); code.len()];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for i in (0..code.len()).rev() {
+            let out: HashSet<String> = successors(i)
+                .into_iter()
+                .flat_map(|s| live_in[s].iter().cloned())
+                .collect();
+
+            let mut inn = out.clone();
+            let (def, uses) = code[i].defs_and_uses();
+            if let Some(d) = def {
+                inn.remove(d);
+            }
+            for u in uses {
+                inn.insert(u.clone());
+            }
+
+            if inn != live_in[i] || out != live_out[i] {
+                changed = true;
+                live_in[i] = inn;
+                live_out[i] = out;
+            }
+        }
+    }
+
+    live_out
+}
+
+// The names that may be read before anything in the code writes them,
+// parameters for a function body
+fn live_on_entry(code: &[IRInstr]) -> HashSet<String> {
+    let Some(first) = code.first() else {
+        return HashSet::new();
+    };
+    let mut live = liveness(code).swap_remove(0);
+    let (def, uses) = first.defs_and_uses();
+    if let Some(d) = def {
+        live.remove(d);
+    }
+    live.extend(uses.into_iter().cloned());
+    live
+}
+
+// -----------------------------
+// Pass: Unreachable code elimination
+// -----------------------------
+//
+// Builds the CFG and walks it from the entry block. Blocks the walk never
+// reaches can't run, e.g. code after a return or the branch of an if whose
+// condition folded to a constant, so their instructions are dropped.
+fn unreachable_code_elimination(mut code: Vec<IRInstr>) -> Vec<IRInstr> {
+    let cfg = build_cfg(&code);
+    if cfg.blocks.is_empty() {
+        return Vec::new();
+    }
+
+    let mut reachable = vec![false; cfg.blocks.len()];
+    let mut stack = vec![0];
+    while let Some(block) = stack.pop() {
+        if reachable[block] {
+            continue;
         }
+        reachable[block] = true;
+        stack.extend(cfg.successors(block));
     }
 
-    code_vec
+    // blocks cover the code in order, so this gives one flag per instruction
+    let mut keep = cfg
+        .blocks
+        .iter()
+        .zip(reachable)
+        .flat_map(|(block, reachable)| std::iter::repeat_n(reachable, block.end - block.start));
+    code.retain(|_| keep.next().unwrap_or(true));
+    code
 }
 
-// Heuristic: treat names that start with 't' followed by digits as temporaries.
-// Adjust if your temp naming scheme differs.
-fn is_temporary_name(name: &str) -> bool {
-    name.starts_with('t') && name[1..].chars().all(|c| c.is_ascii_digit())
+// -----------------------------
+// Pass: Redundant jump removal
+// -----------------------------
+//
+// Once a branch is pruned, what's left is often `goto L1` straight before `L1:`
+// and labels nothing jumps to anymore. Neither changes where control goes, and
+// dropping the labels lets constants flow through for the next round.
+fn remove_redundant_jumps(code: Vec<IRInstr>) -> Vec<IRInstr> {
+    let mut new_code: Vec<IRInstr> = Vec::with_capacity(code.len());
+    let mut instrs = code.into_iter().peekable();
+    while let Some(instr) = instrs.next() {
+        if let IRInstr::Jump(label) = &instr {
+            if matches!(instrs.peek(), Some(IRInstr::Label(next)) if next == label) {
+                continue;
+            }
+        }
+        new_code.push(instr);
+    }
+
+    let targeted = jump_targets(&new_code);
+    new_code.retain(|instr| !matches!(instr, IRInstr::Label(label) if !targeted.contains(label)));
+    new_code
+}
+
+// every label that some Jump or JumpIfFalse goes to
+fn jump_targets(code: &[IRInstr]) -> HashSet<String> {
+    code.iter()
+        .filter_map(|instr| match instr {
+            IRInstr::Jump(label) | IRInstr::JumpIfFalse(_, label) => Some(label.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+// a name as an operand, a Temp when it's one of the generator's temps
+fn name_value(name: &str) -> IRValue {
+    if is_temp_name(name) {
+        IRValue::Temp(name.to_string())
+    } else {
+        IRValue::Var(name.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::target_code_generator::{VMValue, VM};
+
+    fn bin(result: &str, left: &str, op: &str, right: &str) -> IRInstr {
+        IRInstr::BinaryOp(result.to_string(), left.to_string(), op.to_string(), right.to_string())
+    }
+
+    #[test]
+    fn dce_keeps_unused_multiplication() {
+        // s = "ab" * n fails at runtime for a negative n even when s is never read
+        let code = vec![bin("s", "a", "*", "n"), IRInstr::Return(None)];
+        assert_eq!(dead_code_elimination(code.clone(), &[]), code);
+    }
+
+    #[test]
+    fn dce_drops_unused_numeric_multiplication() {
+        let params = [("a".to_string(), Type::Int), ("f".to_string(), Type::Float)];
+        let code = vec![bin("p", "a", "*", "a"), bin("q", "f", "*", "a"), IRInstr::Return(None)];
+        assert_eq!(dead_code_elimination(code, &params), vec![IRInstr::Return(None)]);
+    }
+
+    #[test]
+    fn dce_drops_unused_division_by_nonzero_constant() {
+        let params = [("a".to_string(), Type::Int)];
+        let code = vec![
+            assign("%1", IRValue::Int(2)),
+            bin("q", "a", "/", "%1"),
+            bin("r", "a", "%", "%1"),
+            IRInstr::Return(None),
+        ];
+        assert_eq!(dead_code_elimination(code, &params), vec![IRInstr::Return(None)]);
+    }
+
+    #[test]
+    fn dce_keeps_unused_division_that_may_fail() {
+        // b is unknown and %1 is zero, either one can stop the program
+        let params = [("a".to_string(), Type::Int), ("b".to_string(), Type::Int)];
+        let code = vec![
+            assign("%1", IRValue::Int(0)),
+            bin("q", "a", "/", "b"),
+            bin("r", "a", "%", "%1"),
+            IRInstr::Return(None),
+        ];
+        assert_eq!(dead_code_elimination(code.clone(), &params), code);
+    }
+
+    fn assign(target: &str, value: IRValue) -> IRInstr {
+        IRInstr::Assign(target.to_string(), value)
+    }
+
+    #[test]
+    fn int_times_zero_becomes_zero() {
+        let code = vec![
+            assign("x", IRValue::Int(4)),
+            IRInstr::Label("L1".to_string()),
+            assign("%1", IRValue::Int(0)),
+            bin("%2", "x", "*", "%1"),
+        ];
+        let simplified = algebraic_simplify(code);
+        assert_eq!(simplified[3], assign("%2", IRValue::Int(0)));
+    }
+
+    #[test]
+    fn string_times_zero_is_left_alone() {
+        let code = vec![
+            assign("s", IRValue::Str("ab".to_string())),
+            assign("%1", IRValue::Int(0)),
+            bin("%2", "s", "*", "%1"),
+        ];
+        assert_eq!(algebraic_simplify(code.clone()), code);
+    }
+
+    #[test]
+    fn simplified_operand_keeps_its_kind() {
+        let code = vec![
+            IRInstr::Call("%1".to_string(), "f".to_string(), Vec::new()),
+            assign("%2", IRValue::Int(0)),
+            bin("%3", "%1", "+", "%2"),
+            bin("%4", "x", "+", "%2"),
+        ];
+        let simplified = algebraic_simplify(code);
+        assert_eq!(simplified[2], assign("%3", IRValue::Temp("%1".to_string())));
+        assert_eq!(simplified[3], assign("%4", IRValue::Var("x".to_string())));
+    }
+
+    // main's IR straight out of the generator, before any pass ran
+    fn main_ir(src: &str) -> Vec<IRInstr> {
+        let tokens = crate::lex_layer::tokenize(src).unwrap();
+        let funcs = crate::parse(&tokens).unwrap();
+        crate::generate_ir(&funcs).into_iter().find(|f| f.name == "main").unwrap().code
+    }
+
+    #[test]
+    fn overflowing_fold_is_left_for_runtime() {
+        let fold = |op, a, b| fold_binary(op, &IRValue::Int(a), &IRValue::Int(b));
+        assert!(fold("+", i64::MAX, 1).is_none());
+        assert!(fold("*", i64::MAX, 2).is_none());
+        assert!(fold("-", i64::MIN, 1).is_none());
+        assert!(matches!(fold("-", i64::MAX, 1), Some(IRValue::Int(n)) if n == i64::MAX - 1));
+    }
+
+    #[test]
+    fn assignments_after_return_are_dropped() {
+        let code = vec![
+            assign("x", IRValue::Int(1)),
+            IRInstr::Return(Some("x".to_string())),
+            assign("a", IRValue::Int(2)),
+            assign("b", IRValue::Int(3)),
+            assign("c", IRValue::Int(4)),
+        ];
+        assert_eq!(unreachable_code_elimination(code.clone()), code[..2]);
+    }
+
+    #[test]
+    fn self_copy_is_gone_after_optimizing() {
+        let code = vec![
+            IRInstr::Call("x".to_string(), "f".to_string(), Vec::new()),
+            assign("x", IRValue::Var("x".to_string())),
+            IRInstr::Return(Some("x".to_string())),
+        ];
+        let optimized = optimize_ir(code);
+        let is_self_copy = |instr: &IRInstr| matches!(instr, IRInstr::Assign(t, IRValue::Var(src)) if t == src);
+        assert!(!optimized.iter().any(is_self_copy), "{:?}", optimized);
+        assert_eq!(optimized.len(), 2);
+    }
+
+    #[test]
+    fn same_length_folding_still_converges() {
+        // folding rewrites y and z in place, the round doesn't change the length
+        let code = vec![
+            assign("x", IRValue::Int(2)),
+            IRInstr::BinaryOp("y".to_string(), "x".to_string(), "+".to_string(), "x".to_string()),
+            IRInstr::BinaryOp("z".to_string(), "y".to_string(), "*".to_string(), "y".to_string()),
+            IRInstr::Return(Some("z".to_string())),
+        ];
+        let optimized = optimize_ir(code);
+        assert!(optimized.contains(&assign("z", IRValue::Int(16))), "{:?}", optimized);
+        assert_eq!(optimize_ir(optimized.clone()), optimized);
+    }
+
+    #[test]
+    fn round_cap_stops_the_loop() {
+        // the pruned branch's label only goes away at the end of the first round,
+        // so x's value reaching the add takes a second one
+        let code = main_ir("func main() -> Int { var x = 1; if (nah) { x = 2; } return x + 1; }");
+        let one_round = Optimizer::new().max_iterations(1).build().run(code.clone());
+        let settled = optimize_ir(code);
+        assert!(one_round.iter().any(|instr| matches!(instr, IRInstr::BinaryOp(..))), "{:?}", one_round);
+        assert_eq!(settled, vec![assign("%2", IRValue::Int(2)), IRInstr::Return(Some("%2".to_string()))]);
+    }
+
+    // what main returns with every function optimized at this level
+    fn run_at_level(src: &str, level: u8) -> Option<VMValue> {
+        let tokens = crate::lex_layer::tokenize(src).unwrap();
+        let mut funcs = crate::generate_ir(&crate::parse(&tokens).unwrap());
+        let opt = Optimizer::level(level);
+        for func in &mut funcs {
+            opt.run_function(func);
+        }
+        let prog = crate::target_code_generator::lower_program(&funcs, "main").unwrap();
+        VM::new().run_to_end(&prog).unwrap()
+    }
+
+    #[test]
+    fn copy_of_reassigned_parameter_keeps_its_value() {
+        let src = "func f(x: Int) -> Int { var y = x; x = 5; return y; } func main() -> Int { return f(1); }";
+        assert_eq!(run_at_level(src, 0), Some(VMValue::Int(1)));
+        assert_eq!(run_at_level(src, 2), run_at_level(src, 0));
+    }
+
+    #[test]
+    fn identical_subexpressions_collapse() {
+        let code = vec![bin("%1", "a", "+", "b"), bin("%2", "a", "+", "b"), bin("%3", "%1", "*", "%2")];
+        let eliminated = common_subexpression_elimination(code.clone());
+        assert_eq!(eliminated[1], assign("%2", IRValue::Temp("%1".to_string())));
+    }
+
+    #[test]
+    fn write_to_operand_invalidates_subexpression() {
+        let code = vec![bin("%1", "a", "+", "b"), assign("a", IRValue::Int(1)), bin("%2", "a", "+", "b")];
+        assert_eq!(common_subexpression_elimination(code.clone()), code);
+    }
+
+    #[test]
+    fn dead_store_to_variable_is_removed() {
+        let code = vec![assign("x", IRValue::Int(1)), assign("x", IRValue::Int(2)), IRInstr::Return(Some("x".to_string()))];
+        assert_eq!(dead_code_elimination(code.clone(), &[]), code[1..]);
+    }
+
+    #[test]
+    fn false_if_branch_is_removed() {
+        let optimized = optimize_ir(main_ir("func main() -> Int { if (nah) { return 1; } return 2; }"));
+        assert!(
+            !optimized.iter().any(|instr| matches!(instr, IRInstr::JumpIfFalse(..) | IRInstr::Assign(_, IRValue::Int(1)))),
+            "{:?}",
+            optimized
+        );
+    }
+
+    #[test]
+    fn true_condition_drops_the_jump() {
+        let code = vec![assign("c", IRValue::Bool(true)), IRInstr::JumpIfFalse("c".to_string(), "L1".to_string())];
+        assert_eq!(constant_fold_and_propagate(code.clone()), code[..1]);
+    }
+
+    #[test]
+    fn false_if_compiles_away_entirely() {
+        let optimized = optimize_ir(main_ir("func main() -> Int { var x = 1; if (nah) { var y = 2; } return x; }"));
+        assert_eq!(optimized, vec![assign("x", IRValue::Int(1)), IRInstr::Return(Some("x".to_string()))]);
+    }
+
+    #[test]
+    fn modulo_folds_like_rust_and_skips_zero() {
+        let fold = |a, b| fold_binary("%", &IRValue::Int(a), &IRValue::Int(b));
+        assert_eq!(fold(7, 3), Some(IRValue::Int(1)));
+        assert_eq!(fold(-7, 3), Some(IRValue::Int(-1)));
+        assert_eq!(fold(7, -3), Some(IRValue::Int(1)));
+        assert_eq!(fold(-7, -3), Some(IRValue::Int(-1)));
+        assert_eq!(fold(7, 0), None);
+        // wraps to 0 the same way the VM's Mod does
+        assert_eq!(fold(i64::MIN, -1), Some(IRValue::Int(0)));
+    }
+
+    #[test]
+    fn duplicate_constant_store_is_removed() {
+        let code = vec![assign("%1", IRValue::Int(5)), assign("%1", IRValue::Int(5)), IRInstr::Return(Some("%1".to_string()))];
+        assert_eq!(remove_repeated_const_stores(code.clone()), code[1..]);
+    }
 }
@@ -0,0 +1,440 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::const_eval::repeat;
+use crate::intermediate_code_generator::{IRFunction, IRInstr, IRValue};
+use crate::semantic_analyzer::Type;
+use crate::target_code_generator::{VMError, VMValue, DEFAULT_MAX_DEPTH, DEFAULT_MAX_STEPS};
+
+//
+// ===== REGISTER VM =====
+//
+// A second backend next to the stack VM. Every IR name in a function (params,
+// vars and temps) gets its own register, so each three-address instruction
+// becomes one register instruction like AddR(dst, a, b) with no Load/Store
+// traffic around it. Operators pick what to do from the values they get, so
+// AddR adds two Ints or two Floats (an Int next to a Float is promoted) and
+// concatenates two Strs, and lowering doesn't have to track types.
+//
+
+pub type Reg = usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegInstr {
+    LoadConst(Reg, VMValue), // dst = constant
+    Move(Reg, Reg),          // dst = src
+    AddR(Reg, Reg, Reg),     // dst = a + b, also joins two strings
+    SubR(Reg, Reg, Reg),
+    MulR(Reg, Reg, Reg),     // also repeats a string, "ab" * 3
+    DivR(Reg, Reg, Reg),
+    ModR(Reg, Reg, Reg),
+    EqR(Reg, Reg, Reg),
+    NeR(Reg, Reg, Reg),
+    LtR(Reg, Reg, Reg),
+    GtR(Reg, Reg, Reg),
+    LeR(Reg, Reg, Reg),
+    GeR(Reg, Reg, Reg),
+    NegR(Reg, Reg),
+    NotR(Reg, Reg),
+    MakeArray(Reg, Vec<Reg>),
+    Index(Reg, Reg, Reg),              // dst = array[index]
+    Call(Option<Reg>, usize, Vec<Reg>), // result register (None when it returns nothing), function index, args
+    Print(Reg),
+    Ret(Option<Reg>),
+    Jump(usize),            // index of an instruction in the same function
+    JumpIfFalse(Reg, usize),
+}
+
+// a function's params are its first registers, in order
+#[derive(Debug, Clone)]
+pub struct RegFunction {
+    pub name: String,
+    pub params: usize,
+    pub registers: usize, // how many registers a call needs
+    pub code: Vec<RegInstr>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RegProgram {
+    pub functions: Vec<RegFunction>,
+    pub entry: usize, // index of the function a run starts in
+}
+
+impl fmt::Display for RegInstr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let regs = |regs: &[Reg]| regs.iter().map(|r| format!("r{}", r)).collect::<Vec<_>>().join(", ");
+        match self {
+            RegInstr::LoadConst(dst, VMValue::Str(s)) => write!(f, "LoadConst r{}, {:?}", dst, s),
+            RegInstr::LoadConst(dst, value) => write!(f, "LoadConst r{}, {}", dst, value),
+            RegInstr::Move(dst, src) | RegInstr::NegR(dst, src) | RegInstr::NotR(dst, src) => {
+                let name = match self {
+                    RegInstr::Move(..) => "Move",
+                    RegInstr::NegR(..) => "NegR",
+                    _ => "NotR",
+                };
+                write!(f, "{} r{}, r{}", name, dst, src)
+            }
+            RegInstr::MakeArray(dst, elements) => write!(f, "MakeArray r{}, [{}]", dst, regs(elements)),
+            RegInstr::Call(Some(dst), func, args) => write!(f, "Call r{}, #{}({})", dst, func, regs(args)),
+            RegInstr::Call(None, func, args) => write!(f, "Call #{}({})", func, regs(args)),
+            RegInstr::Print(src) => write!(f, "Print r{}", src),
+            RegInstr::Ret(Some(src)) => write!(f, "Ret r{}", src),
+            RegInstr::Ret(None) => write!(f, "Ret"),
+            RegInstr::Jump(target) => write!(f, "Jump -> {:04}", target),
+            RegInstr::JumpIfFalse(cond, target) => write!(f, "JumpIfFalse r{} -> {:04}", cond, target),
+            //every other instruction is dst, a, b
+            other => {
+                let (name, dst, a, b) = match other {
+                    RegInstr::AddR(d, a, b) => ("AddR", d, a, b),
+                    RegInstr::SubR(d, a, b) => ("SubR", d, a, b),
+                    RegInstr::MulR(d, a, b) => ("MulR", d, a, b),
+                    RegInstr::DivR(d, a, b) => ("DivR", d, a, b),
+                    RegInstr::ModR(d, a, b) => ("ModR", d, a, b),
+                    RegInstr::EqR(d, a, b) => ("EqR", d, a, b),
+                    RegInstr::NeR(d, a, b) => ("NeR", d, a, b),
+                    RegInstr::LtR(d, a, b) => ("LtR", d, a, b),
+                    RegInstr::GtR(d, a, b) => ("GtR", d, a, b),
+                    RegInstr::LeR(d, a, b) => ("LeR", d, a, b),
+                    RegInstr::GeR(d, a, b) => ("GeR", d, a, b),
+                    RegInstr::Index(d, a, b) => ("Index", d, a, b),
+                    _ => unreachable!("every other instruction is matched above"),
+                };
+                write!(f, "{} r{}, r{}, r{}", name, dst, a, b)
+            }
+        }
+    }
+}
+
+// each function's name followed by its instructions, numbered from 0 within the function
+pub fn disassemble_registers(prog: &RegProgram) -> String {
+    let mut out = String::new();
+    for (i, func) in prog.functions.iter().enumerate() {
+        out.push_str(&format!("#{} {} ({} params, {} registers):\n", i, func.name, func.params, func.registers));
+        for (ip, instr) in func.code.iter().enumerate() {
+            out.push_str(&format!("{:04}  {}\n", ip, instr));
+        }
+    }
+    out
+}
+
+// ===== Lowering from IR to registers =====
+
+// Lowers a single function body on its own, like lower_ir_to_vm it can't call anything but print
+pub fn lower_ir_to_registers(ir: &[IRInstr]) -> Result<RegFunction, String> {
+    lower_function("main", &[], ir, &HashMap::new())
+}
+
+// Lowers every function, a call names its callee by index in the program's function list
+pub fn lower_program_to_registers(funcs: &[IRFunction], entry: &str) -> Result<RegProgram, String> {
+    let callees: HashMap<&str, (usize, &Type)> =
+        funcs.iter().enumerate().map(|(i, func)| (func.name.as_str(), (i, &func.return_type))).collect();
+    let functions = funcs
+        .iter()
+        .map(|func| lower_function(&func.name, &func.params, &func.code, &callees))
+        .collect::<Result<_, _>>()?;
+    let entry = callees.get(entry).ok_or_else(|| format!("Call to unknown function '{}'", entry))?.0;
+    Ok(RegProgram { functions, entry })
+}
+
+fn lower_function(
+    name: &str,
+    params: &[(String, Type)],
+    ir: &[IRInstr],
+    callees: &HashMap<&str, (usize, &Type)>,
+) -> Result<RegFunction, String> {
+    // params take the first registers, every other name gets the next free one
+    let mut registers: HashMap<&str, Reg> = HashMap::new();
+    for (param, _) in params {
+        let next = registers.len();
+        registers.entry(param).or_insert(next);
+    }
+    for instr in ir {
+        let (def, uses) = instr.defs_and_uses();
+        for name in def.into_iter().chain(uses) {
+            let next = registers.len();
+            registers.entry(name).or_insert(next);
+        }
+    }
+    let reg = |name: &String| registers[name.as_str()];
+
+    let mut code = Vec::with_capacity(ir.len());
+    let mut labels: HashMap<&str, usize> = HashMap::new();
+    let mut fixups: Vec<(usize, &str)> = Vec::new(); // (jump index, label name)
+    for instr in ir {
+        let lowered = match instr {
+            IRInstr::Assign(target, value) => match value {
+                IRValue::Int(n) => RegInstr::LoadConst(reg(target), VMValue::Int(*n)),
+                IRValue::Float(n) => RegInstr::LoadConst(reg(target), VMValue::Float(*n)),
+                IRValue::Bool(b) => RegInstr::LoadConst(reg(target), VMValue::Bool(*b)),
+                IRValue::Str(s) => RegInstr::LoadConst(reg(target), VMValue::Str(s.clone())),
+                IRValue::Char(c) => RegInstr::LoadConst(reg(target), VMValue::Char(*c)),
+                IRValue::Var(v) | IRValue::Temp(v) => RegInstr::Move(reg(target), reg(v)),
+            },
+            IRInstr::BinaryOp(result, left, op, right) => {
+                let (d, a, b) = (reg(result), reg(left), reg(right));
+                match op.as_str() {
+                    "+" => RegInstr::AddR(d, a, b),
+                    "-" => RegInstr::SubR(d, a, b),
+                    "*" => RegInstr::MulR(d, a, b),
+                    "/" => RegInstr::DivR(d, a, b),
+                    "%" => RegInstr::ModR(d, a, b),
+                    "==" => RegInstr::EqR(d, a, b),
+                    "!=" => RegInstr::NeR(d, a, b),
+                    "<" => RegInstr::LtR(d, a, b),
+                    ">" => RegInstr::GtR(d, a, b),
+                    "<=" => RegInstr::LeR(d, a, b),
+                    ">=" => RegInstr::GeR(d, a, b),
+                    _ => return Err(format!("No register instruction for operator '{}'", op)),
+                }
+            }
+            IRInstr::UnaryOp(result, op, operand) => match op.as_str() {
+                "-" => RegInstr::NegR(reg(result), reg(operand)),
+                "!" => RegInstr::NotR(reg(result), reg(operand)),
+                _ => return Err(format!("No register instruction for operator '{}'", op)),
+            },
+            IRInstr::MakeArray(result, elements) => RegInstr::MakeArray(reg(result), elements.iter().map(reg).collect()),
+            IRInstr::Index(result, base, index) => RegInstr::Index(reg(result), reg(base), reg(index)),
+            IRInstr::Call(_, callee, args) if callee == "print" => match &args[..] {
+                [arg] => RegInstr::Print(reg(arg)),
+                _ => return Err(format!("print takes 1 argument, got {}", args.len())),
+            },
+            IRInstr::Call(result, callee, args) => {
+                let &(index, return_type) =
+                    callees.get(callee.as_str()).ok_or_else(|| format!("Call to unknown function '{}'", callee))?;
+                // a function returning nothing has nothing to put in a register
+                let dst = (*return_type != Type::Unit).then(|| reg(result));
+                RegInstr::Call(dst, index, args.iter().map(reg).collect())
+            }
+            IRInstr::Return(value) => RegInstr::Ret(value.as_ref().map(reg)),
+            IRInstr::Phi(result, _) => return Err(format!("Can't lower phi for '{}', IR has to leave SSA form first", result)),
+            // labels emit nothing, they just name the next instruction
+            IRInstr::Label(label) => {
+                labels.insert(label, code.len());
+                continue;
+            }
+            IRInstr::Jump(label) => {
+                fixups.push((code.len(), label));
+                RegInstr::Jump(0)
+            }
+            IRInstr::JumpIfFalse(cond, label) => {
+                fixups.push((code.len(), label));
+                RegInstr::JumpIfFalse(reg(cond), 0)
+            }
+        };
+        code.push(lowered);
+    }
+
+    // second pass: point every jump at its label
+    for (index, label) in fixups {
+        let target = *labels.get(label).ok_or_else(|| format!("Jump to undefined label '{}'", label))?;
+        match &mut code[index] {
+            RegInstr::Jump(t) | RegInstr::JumpIfFalse(_, t) => *t = target,
+            _ => unreachable!("fixup recorded for a non-jump instruction"),
+        }
+    }
+
+    Ok(RegFunction { name: name.to_string(), params: params.len(), registers: registers.len(), code })
+}
+
+// ===== the register VM itself =====
+
+// a call that's waiting for its callee to return
+struct RegFrame {
+    func: usize,
+    ip: usize,             // where to carry on once the callee returns
+    regs: Vec<VMValue>,
+    result: Option<Reg>,   // the register the callee's value goes into
+}
+
+pub struct RegVM {
+    output: Vec<String>, // lines written by Print
+    max_depth: usize,    // most nested calls before a call fails with StackOverflow
+    max_steps: usize,    // most instructions a run may execute before failing with StepLimitExceeded
+}
+
+impl Default for RegVM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RegVM {
+    pub fn new() -> Self {
+        Self { output: Vec::new(), max_depth: DEFAULT_MAX_DEPTH, max_steps: DEFAULT_MAX_STEPS }
+    }
+
+    /// Everything the program printed so far, one entry per Print
+    pub fn output(&self) -> &[String] {
+        &self.output
+    }
+
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    pub fn set_max_steps(&mut self, steps: usize) {
+        self.max_steps = steps;
+    }
+
+    /// Runs the entry function and returns its value, same results and errors as the stack VM
+    pub fn run(&mut self, prog: &RegProgram) -> Result<Option<VMValue>, VMError> {
+        let mut callers: Vec<RegFrame> = Vec::new();
+        let mut func = prog.entry;
+        let mut ip = 0;
+        // registers start out as 0, the IR always writes a name before reading it
+        let mut regs = vec![VMValue::Int(0); prog.functions[func].registers];
+        let mut steps = 0;
+
+        loop {
+            if steps >= self.max_steps {
+                return Err(VMError::StepLimitExceeded(self.max_steps));
+            }
+            steps += 1;
+            // running off the end of a function is a return with no value
+            let instr = prog.functions[func].code.get(ip).unwrap_or(&RegInstr::Ret(None));
+            ip += 1;
+
+            match instr {
+                RegInstr::LoadConst(dst, value) => regs[*dst] = value.clone(),
+                RegInstr::Move(dst, src) => regs[*dst] = regs[*src].clone(),
+                RegInstr::AddR(d, a, b) => regs[*d] = add(&regs[*a], &regs[*b])?,
+                RegInstr::SubR(d, a, b) => regs[*d] = arithmetic("SubR", &regs[*a], &regs[*b], i64::wrapping_sub, |a, b| a - b)?,
+                RegInstr::MulR(d, a, b) => regs[*d] = mul(&regs[*a], &regs[*b])?,
+                RegInstr::DivR(d, a, b) => {
+                    if matches!(regs[*b], VMValue::Int(0)) && matches!(regs[*a], VMValue::Int(_)) {
+                        return Err(VMError::DivisionByZero);
+                    }
+                    regs[*d] = arithmetic("DivR", &regs[*a], &regs[*b], i64::wrapping_div, |a, b| a / b)?;
+                }
+                RegInstr::ModR(d, a, b) => match (&regs[*a], &regs[*b]) {
+                    (VMValue::Int(_), VMValue::Int(0)) => return Err(VMError::DivisionByZero),
+                    (VMValue::Int(a), VMValue::Int(b)) => regs[*d] = VMValue::Int(a.wrapping_rem(*b)),
+                    (a, b) => return Err(mismatch("ModR expects two integers", a, b)),
+                },
+                RegInstr::EqR(d, a, b) => regs[*d] = VMValue::Bool(regs[*a] == regs[*b]),
+                RegInstr::NeR(d, a, b) => regs[*d] = VMValue::Bool(regs[*a] != regs[*b]),
+                RegInstr::LtR(d, a, b) => regs[*d] = VMValue::Bool(compare(&regs[*a], &regs[*b])?.is_some_and(Ordering::is_lt)),
+                RegInstr::GtR(d, a, b) => regs[*d] = VMValue::Bool(compare(&regs[*a], &regs[*b])?.is_some_and(Ordering::is_gt)),
+                RegInstr::LeR(d, a, b) => regs[*d] = VMValue::Bool(compare(&regs[*a], &regs[*b])?.is_some_and(Ordering::is_le)),
+                RegInstr::GeR(d, a, b) => regs[*d] = VMValue::Bool(compare(&regs[*a], &regs[*b])?.is_some_and(Ordering::is_ge)),
+                RegInstr::NegR(dst, src) => {
+                    regs[*dst] = match &regs[*src] {
+                        VMValue::Int(n) => VMValue::Int(n.wrapping_neg()),
+                        VMValue::Float(n) => VMValue::Float(-n),
+                        other => return Err(VMError::TypeMismatch(format!("NegR expects a number, got {:?}", other))),
+                    }
+                }
+                RegInstr::NotR(dst, src) => {
+                    regs[*dst] = match &regs[*src] {
+                        VMValue::Bool(b) => VMValue::Bool(!b),
+                        other => return Err(VMError::TypeMismatch(format!("NotR expects a boolean, got {:?}", other))),
+                    }
+                }
+                RegInstr::MakeArray(dst, elements) => {
+                    regs[*dst] = VMValue::Array(elements.iter().map(|r| regs[*r].clone()).collect());
+                }
+                RegInstr::Index(dst, base, index) => {
+                    regs[*dst] = match (&regs[*base], &regs[*index]) {
+                        (VMValue::Array(elements), VMValue::Int(i)) => usize::try_from(*i)
+                            .ok()
+                            .and_then(|i| elements.get(i))
+                            .cloned()
+                            .ok_or(VMError::IndexOutOfBounds { index: *i, len: elements.len() })?,
+                        (a, i) => return Err(mismatch("Index expects an array and an integer", a, i)),
+                    }
+                }
+                RegInstr::Print(src) => self.output.push(regs[*src].to_string()),
+
+                // the args are copied into the callee's first registers
+                RegInstr::Call(result, callee, args) => {
+                    if callers.len() + 1 >= self.max_depth {
+                        return Err(VMError::StackOverflow);
+                    }
+                    let target = &prog.functions[*callee];
+                    if target.params != args.len() {
+                        return Err(VMError::ArgumentCount { expected: target.params, got: args.len() });
+                    }
+                    let mut callee_regs = vec![VMValue::Int(0); target.registers];
+                    for (param, arg) in callee_regs.iter_mut().zip(args) {
+                        *param = regs[*arg].clone();
+                    }
+                    let caller_regs = std::mem::replace(&mut regs, callee_regs);
+                    callers.push(RegFrame { func, ip, regs: caller_regs, result: *result });
+                    func = *callee;
+                    ip = 0;
+                }
+                // returning from the entry function ends the run
+                RegInstr::Ret(value) => {
+                    let value = value.map(|r| std::mem::replace(&mut regs[r], VMValue::Int(0)));
+                    let Some(caller) = callers.pop() else {
+                        return Ok(value);
+                    };
+                    func = caller.func;
+                    ip = caller.ip;
+                    regs = caller.regs;
+                    if let (Some(dst), Some(value)) = (caller.result, value) {
+                        regs[dst] = value;
+                    }
+                }
+
+                RegInstr::Jump(target) => ip = *target,
+                RegInstr::JumpIfFalse(cond, target) => match &regs[*cond] {
+                    VMValue::Bool(false) => ip = *target,
+                    VMValue::Bool(true) => {}
+                    other => {
+                        return Err(VMError::TypeMismatch(format!("JumpIfFalse expects a bool, got {:?}", other)))
+                    }
+                },
+            }
+        }
+    }
+}
+
+// two ints, or two floats with an Int next to a Float promoted, like the stack
+// VM's IntToFloat
+fn arithmetic(
+    name: &str,
+    a: &VMValue,
+    b: &VMValue,
+    ints: fn(i64, i64) -> i64,
+    floats: fn(f64, f64) -> f64,
+) -> Result<VMValue, VMError> {
+    match (a, b) {
+        (VMValue::Int(a), VMValue::Int(b)) => Ok(VMValue::Int(ints(*a, *b))),
+        (VMValue::Float(a), VMValue::Float(b)) => Ok(VMValue::Float(floats(*a, *b))),
+        (VMValue::Int(a), VMValue::Float(b)) => Ok(VMValue::Float(floats(*a as f64, *b))),
+        (VMValue::Float(a), VMValue::Int(b)) => Ok(VMValue::Float(floats(*a, *b as f64))),
+        (a, b) => Err(mismatch(&format!("{} expects two numbers", name), a, b)),
+    }
+}
+
+fn add(a: &VMValue, b: &VMValue) -> Result<VMValue, VMError> {
+    match (a, b) {
+        (VMValue::Str(a), VMValue::Str(b)) => Ok(VMValue::Str(format!("{}{}", a, b))),
+        _ => arithmetic("AddR", a, b, i64::wrapping_add, |a, b| a + b),
+    }
+}
+
+fn mul(a: &VMValue, b: &VMValue) -> Result<VMValue, VMError> {
+    match (a, b) {
+        (VMValue::Str(s), VMValue::Int(count)) => {
+            repeat(s, *count).map(VMValue::Str).ok_or(VMError::BadRepeatCount(*count))
+        }
+        _ => arithmetic("MulR", a, b, i64::wrapping_mul, |a, b| a * b),
+    }
+}
+
+// None when a NaN is involved, every comparison with it is false
+fn compare(a: &VMValue, b: &VMValue) -> Result<Option<Ordering>, VMError> {
+    match (a, b) {
+        (VMValue::Int(a), VMValue::Int(b)) => Ok(Some(a.cmp(b))),
+        (VMValue::Str(a), VMValue::Str(b)) => Ok(Some(a.cmp(b))),
+        (VMValue::Float(a), VMValue::Float(b)) => Ok(a.partial_cmp(b)),
+        (a, b) => Err(mismatch("Comparison expects two numbers of the same type or two strings", a, b)),
+    }
+}
+
+fn mismatch(what: &str, a: &VMValue, b: &VMValue) -> VMError {
+    VMError::TypeMismatch(format!("{}, got {:?} and {:?}", what, a, b))
+}
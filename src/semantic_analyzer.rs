@@ -1,142 +1,492 @@
 use std::collections::HashMap;
-use crate::syntax_analyzer::{Expression, Function, Statement};
+use std::fmt;
+use crate::const_eval::eval_const;
+use crate::syntax_analyzer::{Expression, Function, SpannedStatement, Statement};
+use crate::target_code_generator::VMValue;
 
 //Defining possible types
 #[derive(Debug, Clone, PartialEq)]
 pub enum Type {
     Int,
+    Float,
     Bool,
     Str,
+    Char,
+    Array(Box<Type>), // every element has this type
+    Unit,//no value, what functions without a declared return type give back
     Unknown,//fallback type if needed
 }
 
 
+//Warnings don't stop compilation, they're just reported
+pub type Warning = String;
+
+//An error and the line and col of the innermost statement it came from,
+//None for ones about no statement in particular like a missing main
+#[derive(Debug, Clone, PartialEq)]
+pub struct SemanticError {
+    pub msg: String,
+    pub pos: Option<(usize, usize)>,
+}
+
+impl SemanticError {
+    //points the error at this position, unless a statement inside already claimed it
+    fn or_at(self, line: usize, col: usize) -> Self {
+        Self { pos: self.pos.or(Some((line, col))), ..self }
+    }
+}
+
+impl From<String> for SemanticError {
+    fn from(msg: String) -> Self {
+        Self { msg, pos: None }
+    }
+}
+
+impl fmt::Display for SemanticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+//What the table knows about a single var
+struct Symbol {
+    ty: Type,
+    used: bool, // has the var been read since it was declared
+    mutable: bool, // false for a const, which can't be assigned to
+}
+
+//Stack of scopes, the last one is the innermost block
 pub struct SymbolTable {
-    variables: HashMap<String, Type>,
+    scopes: Vec<HashMap<String, Symbol>>,
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SymbolTable {
     pub fn new() -> Self {
         Self {
-            variables: HashMap::new(),
+            scopes: vec![HashMap::new()],
         }
     }
 
-    //Inserts vars into table and checks if it already exists in scope
-    pub fn insert(&mut self, name: String, ty: Type) -> Result<(), String> {
-        if self.variables.contains_key(&name) {
+    //Starts a new block, vars declared in it disappear at exit_scope
+    pub fn enter_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    //Ends the innermost block and returns the names of its vars that were never read
+    pub fn exit_scope(&mut self) -> Vec<String> {
+        let scope = self.scopes.pop().unwrap_or_default();
+        let mut unused: Vec<String> = scope
+            .into_iter()
+            .filter(|(name, sym)| !sym.used && !name.starts_with('_'))
+            .map(|(name, _)| name)
+            .collect();
+        unused.sort();
+        unused
+    }
+
+    //Inserts vars into table and checks if it already exists in the current scope.
+    //Outer scopes are fine since the new var just shadows them
+    pub fn insert(&mut self, name: String, ty: Type, mutable: bool) -> Result<(), String> {
+        let scope = self.scopes.last_mut().expect("No scope");
+        if scope.contains_key(&name) {
             return Err(format!("Variable '{}' already declared", name));
         }
-        self.variables.insert(name, ty);
+        scope.insert(name, Symbol { ty, used: false, mutable });
         Ok(())
     }
 
-    //Looks up type of var
+    //Looks up type of var, innermost scope first
     pub fn lookup(&self, name: &str) -> Option<&Type> {
-        self.variables.get(name)
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).map(|sym| &sym.ty)
+    }
+
+    //Whether the var a name currently refers to was declared with const
+    pub fn is_const(&self, name: &str) -> bool {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).is_some_and(|sym| !sym.mutable)
+    }
+
+    //Records that the var a name currently refers to was read
+    pub fn mark_used(&mut self, name: &str) {
+        if let Some(sym) = self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name)) {
+            sym.used = true;
+        }
     }
 }
 
 
+//What a call needs to know about a function
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionSig {
+    pub params: Vec<Type>,
+    pub return_type: Type, // Unit when it doesn't give back a value
+}
+
 pub struct SemanticAnalyzer {
     symbols: SymbolTable, // keeps track of vars and their types
+    functions: HashMap<String, FunctionSig>, // every function a call can reach
+    current: Option<(String, Type)>, // function being analyzed and its declared return type
+    loop_depth: usize, // how many loops the current statement is inside, break needs at least one
+    warnings: Vec<Warning>, // collected while analyzing the current function
+    returns: Vec<(Type, (usize, usize))>, // type and position of every return in the current function, in order
+}
+
+impl Default for SemanticAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SemanticAnalyzer {
     //this creates a new analyzer with empty symbol tables
     pub fn new() -> Self {
-        Self { symbols: SymbolTable::new() }
+        //print is built in and takes a single value of any type
+        let print = FunctionSig { params: vec![Type::Unknown], return_type: Type::Unit };
+        let functions = HashMap::from([("print".to_string(), print)]);
+        Self {
+            symbols: SymbolTable::new(),
+            functions,
+            current: None,
+            loop_depth: 0,
+            warnings: Vec::new(),
+            returns: Vec::new(),
+        }
     }
 
-    //goes through everything in the function body
-    pub fn analyze_function(&mut self, func: &Function) -> Result<(), String> {
-        for stmt in &func.body {
-            self.analyze_statement(stmt)?;
+    //makes a function callable from the bodies analyzed after this
+    pub fn declare_function(&mut self, name: &str, sig: FunctionSig) {
+        self.functions.insert(name.to_string(), sig);
+    }
+
+    //declares every function up front, so a body can call one defined after it
+    //and two functions can call each other
+    pub fn collect_signatures(&mut self, funcs: &[Function]) {
+        for func in funcs {
+            self.declare_function(&func.name, signature(func));
         }
-        Ok(())
     }
 
-    //analyzes single statement
-    fn analyze_statement(&mut self, stmt: &Statement) -> Result<(), String> {
+    //goes through everything in the function body, returns any warnings found
+    pub fn analyze_function(&mut self, func: &Function) -> Result<Vec<Warning>, SemanticError> {
+        let return_type = func.return_type.clone().unwrap_or(Type::Unit);
+        //a function can always call itself
+        self.declare_function(&func.name, signature(func));
+        self.current = Some((func.name.clone(), return_type.clone()));
+        self.warnings.clear();
+        self.returns.clear();
+
+        //params live in the function's outermost scope along with its top level vars
+        self.symbols.enter_scope();
+        let result = func
+            .params
+            .iter()
+            .try_for_each(|(name, ty)| self.symbols.insert(name.clone(), ty.clone(), true))
+            .map_err(|e| SemanticError::from(e).or_at(func.line, func.col))
+            .and_then(|_| func.body.iter().try_for_each(|stmt| self.analyze_statement(stmt)));
+        self.report_unreachable(&func.body);
+        self.report_unused();
+        result?;
+
+        //every return has to give back the same type, declared or not. The
+        //error points at the first one that disagrees
+        let mut known = self.returns.iter().filter(|(ty, _)| *ty != Type::Unknown);
+        if let Some((first, _)) = known.next() {
+            if let Some((other, pos)) = known.find(|(ty, _)| ty != first) {
+                return Err(SemanticError {
+                    msg: format!("function '{}' returns both {:?} and {:?}", func.name, first, other),
+                    pos: Some(*pos),
+                });
+            }
+        }
+        if return_type == Type::Unit {
+            if let Some((_, pos)) = self.returns.iter().find(|(ty, _)| *ty != Type::Unit) {
+                return Err(SemanticError {
+                    msg: format!("function '{}' has no return type but returns a value", func.name),
+                    pos: Some(*pos),
+                });
+            }
+        }
+
+        //a declared return type means every path has to hit a return
+        if return_type != Type::Unit && !always_returns(&func.body) {
+            return Err(SemanticError {
+                msg: format!(
+                    "function '{}' declares return type {:?} but not every path returns a value",
+                    func.name, return_type
+                ),
+                pos: Some((func.line, func.col)),
+            });
+        }
+        Ok(std::mem::take(&mut self.warnings))
+    }
+
+    //type of an expression on its own, outside any function
+    pub fn expression_type(&mut self, expr: &Expression) -> Result<Type, String> {
+        self.analyze_expression(expr)
+    }
+
+    //analyzes single statement, an error that doesn't come from a statement
+    //nested inside it points at its start
+    fn analyze_statement(&mut self, stmt: &SpannedStatement) -> Result<(), SemanticError> {
+        self.check_statement(&stmt.stmt, (stmt.line, stmt.col))
+            .map_err(|e| e.or_at(stmt.line, stmt.col))
+    }
+
+    fn check_statement(&mut self, stmt: &Statement, pos: (usize, usize)) -> Result<(), SemanticError> {
         match stmt {
             //variable declaration
-            Statement::VarDecl { name, value } => {
-                let ty = self.analyze_expression(value)?;
-                self.symbols.insert(name.clone(), ty)?;
+            Statement::VarDecl { name, value, mutable } => {
+                //an outer var with the same name is fine to read, it's being shadowed
+                if self.symbols.lookup(name).is_none() && mentions(value, name) {
+                    return Err(format!("variable '{}' used in its own initializer", name).into());
+                }
+                let ty = self.analyze_value(value)?;
+                self.symbols.insert(name.clone(), ty, *mutable)?;
             }
-            //checks type of return statement
+            //the var has to exist already and keep its type
+            Statement::Assign { name, value } => {
+                let var_ty = self
+                    .symbols
+                    .lookup(name)
+                    .cloned()
+                    .ok_or_else(|| format!("Assignment to undeclared variable '{}'", name))?;
+                if self.symbols.is_const(name) {
+                    return Err(format!("cannot assign to constant '{}'", name).into());
+                }
+                let ty = self.analyze_value(value)?;
+                if var_ty != Type::Unknown && ty != Type::Unknown && ty != var_ty {
+                    return Err(format!(
+                        "Cannot assign {:?} to variable '{}' of type {:?}",
+                        ty, name, var_ty
+                    ).into());
+                }
+            }
+            //checks type of return statement, a bare return gives back Unit
             Statement::Return(expr) => {
-                let _ty = self.analyze_expression(expr)?;
-                // later: check against function return type
+                let ty = match expr {
+                    Some(expr) => self.analyze_value(expr)?,
+                    None => Type::Unit,
+                };
+                //without a declared type the returns are only compared with each
+                //other, once the whole body has been seen
+                self.returns.push((ty.clone(), pos));
+                if let Some((name, declared)) = &self.current {
+                    if *declared != Type::Unit && ty == Type::Unit {
+                        return Err(format!(
+                            "function '{}' declares return type {:?} but returns nothing",
+                            name, declared
+                        ).into());
+                    }
+                    if *declared != Type::Unit && ty != Type::Unknown && ty != *declared {
+                        return Err(format!(
+                            "function '{}' declares return type {:?} but returns {:?}",
+                            name, declared, ty
+                        ).into());
+                    }
+                }
             }
-            //type check the expression
+            //type check the expression, its value is thrown away so any type
+            //is fine, Unit from a call that returns nothing included
             Statement::Expr(expr) => {
                 self.analyze_expression(expr)?;
             }
+            //condition has to be a Bool, then check both branches
+            Statement::If { cond, then_body, else_body } => {
+                let cond_ty = self.analyze_value(cond)?;
+                if cond_ty != Type::Bool && cond_ty != Type::Unknown {
+                    return Err(format!("Condition of 'if' must be Bool, found {:?}", cond_ty).into());
+                }
+                self.analyze_block(then_body)?;
+                if let Some(else_body) = else_body {
+                    self.analyze_block(else_body)?;
+                }
+            }
+            Statement::While { cond, body } => {
+                let cond_ty = self.analyze_value(cond)?;
+                if cond_ty != Type::Bool && cond_ty != Type::Unknown {
+                    return Err(format!("Condition of 'while' must be Bool, found {:?}", cond_ty).into());
+                }
+                self.loop_depth += 1;
+                let result = self.analyze_block(body);
+                self.loop_depth -= 1;
+                result?;
+            }
+            Statement::Break | Statement::Continue if self.loop_depth == 0 => {
+                let keyword = if matches!(stmt, Statement::Break) { "break" } else { "continue" };
+                return Err(format!("'{}' outside of a loop", keyword).into());
+            }
+            Statement::Break | Statement::Continue => {}
+            Statement::Block(body) => self.analyze_block(body)?,
         }
         Ok(())
     }
 
+    //analyzes statements in their own scope and warns about vars it never read
+    fn analyze_block(&mut self, stmts: &[SpannedStatement]) -> Result<(), SemanticError> {
+        self.symbols.enter_scope();
+        let result = stmts.iter().try_for_each(|stmt| self.analyze_statement(stmt));
+        self.report_unreachable(stmts);
+        self.report_unused();
+        result
+    }
+
+    //warns once per block when statements follow one that always returns
+    fn report_unreachable(&mut self, stmts: &[SpannedStatement]) {
+        let returns_at = stmts.iter().position(|stmt| always_returns(std::slice::from_ref(stmt)));
+        if returns_at.is_some_and(|i| i + 1 < stmts.len()) {
+            self.warnings.push("unreachable statement after return".to_string());
+        }
+    }
+
+    //closes the innermost scope, warning about anything in it that was never read
+    fn report_unused(&mut self) {
+        for name in self.symbols.exit_scope() {
+            self.warnings.push(format!("variable '{}' is declared but never used", name));
+        }
+    }
+
+    //like analyze_expression, for places that need an actual value
+    fn analyze_value(&mut self, expr: &Expression) -> Result<Type, String> {
+        let ty = self.analyze_expression(expr)?;
+        if ty == Type::Unit {
+            //only calls can come out as Unit
+            let what = match expr {
+                Expression::Call { name, .. } => format!("'{}'", name),
+                _ => "expression".to_string(),
+            };
+            return Err(format!("{} returns nothing, so it can't be used as a value", what));
+        }
+        Ok(ty)
+    }
+
+    //like analyze_value, with an error naming the operator that got a Unit operand
+    fn analyze_operand(&mut self, op: &str, expr: &Expression) -> Result<Type, String> {
+        let ty = self.analyze_expression(expr)?;
+        if ty == Type::Unit {
+            let what = match expr {
+                Expression::Call { name, .. } => format!("'{}()'", name),
+                _ => "this operand".to_string(),
+            };
+            return Err(format!("Operator '{}' can't be applied to {}, it returns nothing (Unit)", op, what));
+        }
+        Ok(ty)
+    }
+
     //analyze expression and its return type
     fn analyze_expression(&mut self, expr: &Expression) -> Result<Type, String> {
         match expr {
 
             Expression::Integer(_) => Ok(Type::Int),
+            Expression::Float(_) => Ok(Type::Float),
             Expression::Boolean(_) => Ok(Type::Bool),
             Expression::String(_) => Ok(Type::Str),
+            Expression::Char(_) => Ok(Type::Char),
 
             //Look up var types
             Expression::Ident(name) => {
-                self.symbols
+                let ty = self
+                    .symbols
                     .lookup(name)
                     .cloned()
-                    .ok_or_else(|| format!("Use of undeclared variable '{}'", name))
+                    .ok_or_else(|| format!("Use of undeclared variable '{}'", name))?;
+                self.symbols.mark_used(name);
+                Ok(ty)
+            }
+
+            //- negates an Int or Float, ! flips a Bool
+            Expression::Unary { op, operand } => {
+                let ty = self.analyze_operand(op, operand)?;
+                if op == "-" && ty == Type::Float {
+                    return Ok(Type::Float);
+                }
+                let expected = if op == "-" { Type::Int } else { Type::Bool };
+                if ty != Type::Unknown && ty != expected {
+                    return Err(format!("Operator '{}' needs {:?}, found {:?}", op, expected, ty));
+                }
+                Ok(expected)
             }
 
             //Binary operations
             Expression::BinaryOp { left, op, right } => {
-                let left_ty = self.analyze_expression(left)?;
-                let right_ty = self.analyze_expression(right)?;
+                let left_ty = self.analyze_operand(op, left)?;
+                let right_ty = self.analyze_operand(op, right)?;
+
+                if left_ty == Type::Unknown || right_ty == Type::Unknown {
+                    return Ok(match op.as_str() {
+                        "==" | "!=" | "<" | ">" | "<=" | ">=" | "&&" | "||" => Type::Bool,
+                        _ => Type::Unknown,
+                    });
+                }
 
+                //an Int mixed with a Float is promoted, so 3 + 2.5 is the Float 5.5
+                let numbers = [Type::Int, Type::Float];
+                if left_ty != right_ty
+                    && numbers.contains(&left_ty)
+                    && numbers.contains(&right_ty)
+                    && matches!(op.as_str(), "+" | "-" | "*" | "/")
+                {
+                    return Ok(Type::Float);
+                }
+
+                //"ab" * 3 repeats the string, giving "ababab"
+                if op == "*" && left_ty == Type::Str && right_ty == Type::Int {
+                    //a count that's always negative would always fail at runtime
+                    if let Some(VMValue::Int(count)) = eval_const(right) {
+                        if count < 0 {
+                            return Err(format!("can't repeat a string {} times, the count can't be negative", count));
+                        }
+                    }
+                    return Ok(Type::Str);
+                }
+
+                //past this point both sides have the same type
                 if left_ty != right_ty {
-                    return Err(format!(
-                        "Type mismatch in binary op '{}': {:?} vs {:?}",
-                        op, left_ty, right_ty
-                    ));
+                    return Err(operator_error(op, &left_ty, &right_ty));
                 }
 
                 //checks op
                 match op.as_str() {
-                    //+ works with Int and Str
+                    //+ works with numbers and Str
                     "+" => {
-                        if left_ty == Type::Int && right_ty == Type::Int {
-                            Ok(Type::Int)
-                        } else if left_ty == Type::Str && right_ty == Type::Str {
-                            Ok(Type::Str)
+                        if matches!(left_ty, Type::Int | Type::Float | Type::Str) {
+                            Ok(left_ty)
                         } else {
-                            Err(format!(
-                                "Operator '+' not supported between {:?} and {:?}",
-                                left_ty, right_ty
-                            ))
+                            Err(operator_error(op, &left_ty, &right_ty))
                         }
                     }
-                    //Only ints
-                    "-" | "*" | "/" => {
-                        if left_ty == Type::Int && right_ty == Type::Int {
-                            Ok(Type::Int)
+                    //Only numbers, and % only ints
+                    "-" | "*" | "/" | "%" => {
+                        //a right side that is always 0 would always fail at runtime
+                        if (op == "/" || op == "%") && eval_const(right) == Some(VMValue::Int(0)) {
+                            return Err(format!("division by zero: right side of '{}' is 0", op));
+                        }
+                        if left_ty == Type::Int || (left_ty == Type::Float && op != "%") {
+                            Ok(left_ty)
+                        } else {
+                            Err(operator_error(op, &left_ty, &right_ty))
+                        }
+                    }
+                    //any two values of the same type can be compared for equality
+                    "==" | "!=" => Ok(Type::Bool),
+                    //both sides of a logical op are conditions
+                    "&&" | "||" => {
+                        if left_ty == Type::Bool {
+                            Ok(Type::Bool)
                         } else {
-                            Err(format!("Operator '{}' not supported for {:?}", op, left_ty))
+                            Err(operator_error(op, &left_ty, &right_ty))
                         }
                     }
-                    //Comparisons only work with same types
-                    "==" | "!=" => {
-                        if left_ty == right_ty {
+                    //numbers order by value, strings alphabetically
+                    "<" | ">" | "<=" | ">=" => {
+                        if matches!(left_ty, Type::Int | Type::Float | Type::Str) {
                             Ok(Type::Bool)
                         } else {
-                            Err(format!(
-                                "Cannot compare values of different types: {:?} vs {:?}",
-                                left_ty, right_ty
-                            ))
+                            Err(operator_error(op, &left_ty, &right_ty))
                         }
                     }
                     //any other operator is unknown
@@ -144,6 +494,248 @@ impl SemanticAnalyzer {
                 }
 
             }
+
+            //callee has to exist and get the right number and types of arguments
+            Expression::Call { name, args } => {
+                let sig = self
+                    .functions
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| format!("Call to undefined function '{}'", name))?;
+                if args.len() != sig.params.len() {
+                    return Err(format!(
+                        "function '{}' expects {} arguments, got {}",
+                        name, sig.params.len(), args.len()
+                    ));
+                }
+                for (i, (arg, expected)) in args.iter().zip(&sig.params).enumerate() {
+                    let ty = self.analyze_value(arg)?;
+                    //print's param is Unknown since it takes anything
+                    if ty != Type::Unknown && *expected != Type::Unknown && ty != *expected {
+                        return Err(format!(
+                            "argument {} to '{}' has type {:?}, expected {:?}",
+                            i + 1, name, ty, expected
+                        ));
+                    }
+                }
+                Ok(sig.return_type)
+            }
+
+            //every element has to match the first one
+            Expression::Array(elements) => {
+                let Some((first, rest)) = elements.split_first() else {
+                    return Err("Empty array literal, its element type can't be worked out".to_string());
+                };
+                let elem_ty = self.analyze_value(first)?;
+                for elem in rest {
+                    let ty = self.analyze_value(elem)?;
+                    if ty != elem_ty {
+                        return Err(format!("Array elements must all have the same type: {:?} vs {:?}", elem_ty, ty));
+                    }
+                }
+                Ok(Type::Array(Box::new(elem_ty)))
+            }
+
+            Expression::Index { base, index } => {
+                let index_ty = self.analyze_value(index)?;
+                if index_ty != Type::Int && index_ty != Type::Unknown {
+                    return Err(format!("Array index must be Int, found {:?}", index_ty));
+                }
+                match self.analyze_value(base)? {
+                    Type::Array(elem_ty) => Ok(*elem_ty),
+                    Type::Unknown => Ok(Type::Unknown),
+                    other => Err(format!("Cannot index into {:?}, only arrays can be indexed", other)),
+                }
+            }
         }
     }
 }
+
+//the param types and return type from a function's definition
+fn signature(func: &Function) -> FunctionSig {
+    FunctionSig {
+        params: func.params.iter().map(|(_, ty)| ty.clone()).collect(),
+        return_type: func.return_type.clone().unwrap_or(Type::Unit),
+    }
+}
+
+//true if running these statements always ends in a return, an if only
+//counts when both of its branches do and a block when its body does
+fn always_returns(stmts: &[SpannedStatement]) -> bool {
+    stmts.iter().any(|stmt| match &stmt.stmt {
+        Statement::Return(_) => true,
+        Statement::If { then_body, else_body: Some(else_body), .. } => {
+            always_returns(then_body) && always_returns(else_body)
+        }
+        Statement::Block(body) => always_returns(body),
+        _ => false,
+    })
+}
+
+//the error for a binary operator that doesn't take these operand types, like
+//"arithmetic operator '-' cannot be applied to Bool". A type both sides share is named once
+fn operator_error(op: &str, left: &Type, right: &Type) -> String {
+    let kind = match op {
+        "+" | "-" | "*" | "/" | "%" => "arithmetic",
+        "==" | "!=" => "equality",
+        "<" | ">" | "<=" | ">=" => "comparison",
+        _ => "logical",
+    };
+    let types = if left == right { format!("{:?}", left) } else { format!("{:?} and {:?}", left, right) };
+    format!("{} operator '{}' cannot be applied to {}", kind, op, types)
+}
+
+//true if the expression reads the var with this name anywhere inside it
+fn mentions(expr: &Expression, name: &str) -> bool {
+    match expr {
+        Expression::Ident(ident) => ident == name,
+        Expression::BinaryOp { left, right, .. } => mentions(left, name) || mentions(right, name),
+        Expression::Call { args, .. } => args.iter().any(|arg| mentions(arg, name)),
+        Expression::Unary { operand, .. } => mentions(operand, name),
+        Expression::Array(elements) => elements.iter().any(|elem| mentions(elem, name)),
+        Expression::Index { base, index } => mentions(base, name) || mentions(index, name),
+        Expression::Integer(_)
+        | Expression::Float(_)
+        | Expression::Boolean(_)
+        | Expression::String(_)
+        | Expression::Char(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SemanticError;
+    use crate::lex_layer::tokenize;
+    use crate::CompileError;
+
+    // analyzes a whole program, the error when it's rejected
+    fn check(src: &str) -> Result<(), SemanticError> {
+        let tokens = tokenize(src).expect("test source should lex");
+        let funcs = crate::parse(&tokens).expect("test source should parse");
+        match crate::analyze(&funcs) {
+            Ok(_) => Ok(()),
+            Err(CompileError::Semantic(e)) => Err(e),
+            Err(e) => panic!("unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn returns_of_different_types_conflict() {
+        let err = check("func f(b: Bool) {\n  if (b) { return 1; }\n  return \"x\";\n}\nfunc main() -> Int { return 1; }")
+            .unwrap_err();
+        assert!(err.msg.contains("returns both Int and Str"), "{}", err);
+        assert_eq!(err.pos, Some((3, 3)));
+    }
+
+    #[test]
+    fn value_return_without_declared_type_is_rejected() {
+        let err = check("func f() { return 1; } func main() -> Int { return 1; }").unwrap_err();
+        assert!(err.msg.contains("has no return type but returns a value"), "{}", err);
+    }
+
+    #[test]
+    fn error_points_at_innermost_statement() {
+        let err = check("func main() -> Int {\n  if (yeah) {\n    var y = x;\n  }\n  return 1;\n}").unwrap_err();
+        assert!(err.msg.contains("undeclared variable 'x'"), "{}", err);
+        assert_eq!(err.pos, Some((3, 5)));
+    }
+
+    #[test]
+    fn missing_main_has_no_position() {
+        let err = check("func f() { }").unwrap_err();
+        assert_eq!(err.pos, None);
+    }
+
+    //wraps a body in a main with no return type
+    fn check_main(body: &str) -> Result<(), SemanticError> {
+        check(&format!("func main() {{ {} }}", body))
+    }
+
+    fn error_of(body: &str) -> String {
+        check_main(body).expect_err("body should be rejected").msg
+    }
+
+    #[test]
+    fn inner_scope_var_does_not_leak() {
+        assert!(error_of("if (yeah) { var x = 1; } var y = x;").contains("undeclared variable 'x'"));
+        assert!(error_of("{ var x = 1; } var y = x;").contains("undeclared variable 'x'"));
+        assert_eq!(check_main("var x = 1; { var x = yeah; } var y = x + 1;"), Ok(()));
+    }
+
+    #[test]
+    fn parameters_are_in_scope_in_the_body() {
+        assert_eq!(check("func f(a: Int, b: Int) -> Int { return a + b; } func main() { }"), Ok(()));
+        assert!(check("func f(a: Int) -> Int { return b; } func main() { }").unwrap_err().msg.contains("undeclared variable 'b'"));
+    }
+
+    #[test]
+    fn self_referential_declaration() {
+        assert!(error_of("var x = x + 1;").contains("variable 'x' used in its own initializer"));
+        assert!(error_of("var x = y;").contains("undeclared variable 'y'"));
+    }
+
+    #[test]
+    fn assigning_an_undeclared_variable_is_rejected() {
+        assert!(error_of("x = 1;").contains("Assignment to undeclared variable 'x'"));
+        assert_eq!(check_main("var x = 1; x = x + 1;"), Ok(()));
+    }
+
+    #[test]
+    fn division_by_constant_zero() {
+        assert!(error_of("var a = 10 / 0;").contains("division by zero"));
+        assert!(error_of("var a = 10 % 0;").contains("division by zero"));
+        assert!(error_of("var x = 4; var a = x / (2 - 2);").contains("division by zero"));
+        assert_eq!(check_main("var x = 4; var a = x / (2 - 1);"), Ok(()));
+    }
+
+    #[test]
+    fn constants_can_be_read_but_not_assigned() {
+        assert_eq!(check_main("const pi = 3; var r = pi * 2;"), Ok(()));
+        assert!(error_of("const pi = 3; pi = 4;").contains("cannot assign to constant 'pi'"));
+    }
+
+    #[test]
+    fn function_can_call_one_defined_later() {
+        assert_eq!(check("func main() -> Int { return g(1); } func g(n: Int) -> Int { return n; }"), Ok(()));
+    }
+
+    #[test]
+    fn call_arity_and_argument_types_are_checked() {
+        let add = "func add(a: Int, b: Int) -> Int { return a + b; }";
+        let err = check(&format!("{} func main() {{ var x = add(1, 2, 3); }}", add)).unwrap_err();
+        assert!(err.msg.contains("function 'add' expects 2 arguments, got 3"), "{}", err);
+        let err = check(&format!("{} func main() {{ var x = add(\"a\", 2); }}", add)).unwrap_err();
+        assert!(err.msg.contains("argument 1 to 'add' has type Str, expected Int"), "{}", err);
+    }
+
+    #[test]
+    fn int_and_float_mix_to_float() {
+        assert_eq!(check("func main() -> Float { return 3 + 2.5; }"), Ok(()));
+        assert_eq!(check("func main() -> Int { return 3 + 2; }"), Ok(()));
+        assert!(check("func main() -> Int { return 3 + 2.5; }").is_err());
+    }
+
+    #[test]
+    fn break_outside_a_loop_is_rejected() {
+        assert!(error_of("break;").contains("'break' outside of a loop"));
+        assert!(error_of("continue;").contains("'continue' outside of a loop"));
+        assert_eq!(check_main("while (yeah) { break; }"), Ok(()));
+    }
+
+    #[test]
+    fn arithmetic_on_bools_names_the_operator() {
+        for op in ["+", "-", "*", "/", "%"] {
+            let msg = error_of(&format!("var a = yeah {} nah;", op));
+            assert!(msg.contains(&format!("arithmetic operator '{}' cannot be applied to Bool", op)), "{}", msg);
+        }
+        assert!(error_of("var a = 1 - yeah;").contains("arithmetic operator '-' cannot be applied to Int and Bool"));
+        assert!(error_of("var a = yeah < nah;").contains("comparison operator '<' cannot be applied to Bool"));
+    }
+
+    #[test]
+    fn string_repeat_types_as_str() {
+        assert_eq!(check("func main() -> Str { return \"ab\" * 3; }"), Ok(()));
+        assert!(error_of("var s = \"ab\" * -1;").contains("can't be negative"));
+        assert!(error_of("var s = 3 * \"ab\";").contains("arithmetic operator '*' cannot be applied to Int and Str"));
+    }
+}
@@ -0,0 +1,402 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::cfg::{build_cfg, Cfg};
+use crate::intermediate_code_generator::{IRInstr, IRValue};
+
+//
+// ===== SSA CONSTRUCTION =====
+//
+// Rewrites a function's IR so every name is written exactly once. Each write
+// gets a new version (x_1, x_2, ...) and reads are renamed to the version that
+// reaches them. Where versions from different paths meet, at the start of a
+// block with more than one predecessor, a Phi picks the one for the path that
+// was taken. Phis only go where the name is still read afterwards.
+//
+// Names read before anything writes them, like params, keep their name. Blocks
+// that can't be reached from the entry are dropped, they have no place in the
+// dominator tree.
+//
+
+pub fn to_ssa(code: &[IRInstr]) -> Vec<IRInstr> {
+    let cfg = build_cfg(code);
+    if cfg.blocks.is_empty() {
+        return Vec::new();
+    }
+    // a loop right at the start makes the entry a join point. An empty block in
+    // front of it gives the phis there a path for coming in from the start
+    if cfg.predecessors(0).next().is_some() {
+        let padded: Vec<IRInstr> = std::iter::once(IRInstr::Label(String::new())).chain(code.iter().cloned()).collect();
+        let mut out = to_ssa(&padded);
+        out.remove(0);
+        return out;
+    }
+    let preds: Vec<Vec<usize>> = (0..cfg.blocks.len()).map(|b| cfg.predecessors(b).collect()).collect();
+    let order = reverse_postorder(&cfg);
+    let idom = immediate_dominators(&cfg, &preds, &order);
+    let reachable = |b: usize| idom[b].is_some();
+
+    // phis[b] holds (original name, result, one source per reachable predecessor in preds order)
+    let mut phis: Vec<Vec<(String, String, Vec<String>)>> = vec![Vec::new(); cfg.blocks.len()];
+    let live_in = live_in(code, &cfg, &preds);
+    let frontiers = dominance_frontiers(&preds, &idom);
+    for (name, def_blocks) in def_blocks(code, &cfg) {
+        let mut work: Vec<usize> = def_blocks.into_iter().filter(|&b| reachable(b)).collect();
+        let mut has_phi = HashSet::new();
+        while let Some(b) = work.pop() {
+            for &join in &frontiers[b] {
+                if live_in[join].contains(name) && has_phi.insert(join) {
+                    let sources = preds[join].iter().filter(|&&p| reachable(p)).count();
+                    phis[join].push((name.to_string(), String::new(), vec![name.to_string(); sources]));
+                    work.push(join);
+                }
+            }
+        }
+    }
+    // same phi order every run, whatever order the HashMap handed the names out in
+    for block_phis in &mut phis {
+        block_phis.sort();
+    }
+
+    let mut children: Vec<Vec<usize>> = vec![Vec::new(); cfg.blocks.len()];
+    for &b in order.iter().skip(1) {
+        if let Some(parent) = idom[b] {
+            children[parent].push(b);
+        }
+    }
+
+    let mut renamer = Renamer {
+        code,
+        cfg: &cfg,
+        preds: &preds,
+        idom: &idom,
+        children: &children,
+        taken: code.iter().flat_map(names).map(str::to_string).collect(),
+        versions: HashMap::new(),
+        current: HashMap::new(),
+        phis,
+        blocks: vec![Vec::new(); cfg.blocks.len()],
+    };
+    renamer.rename(0);
+
+    // blocks go back in their original order, each with its phis right after its label
+    let mut out = Vec::with_capacity(code.len());
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        if !reachable(b) {
+            continue;
+        }
+        let mut instrs = std::mem::take(&mut renamer.blocks[b]).into_iter();
+        if let IRInstr::Label(_) = code[block.start] {
+            out.extend(instrs.next());
+        }
+        for (_, result, sources) in std::mem::take(&mut renamer.phis[b]) {
+            out.push(IRInstr::Phi(result, sources));
+        }
+        out.extend(instrs);
+    }
+    out
+}
+
+// walks the dominator tree handing out versions, so a read always sees the
+// version written by the closest write above it
+struct Renamer<'a> {
+    code: &'a [IRInstr],
+    cfg: &'a Cfg,
+    preds: &'a [Vec<usize>],
+    idom: &'a [Option<usize>],
+    children: &'a [Vec<usize>],
+    taken: HashSet<String>,                    // every name in use, so a new version never clashes
+    versions: HashMap<String, usize>,          // last version number handed out for each name
+    current: HashMap<String, Vec<String>>,     // versions visible right now, innermost last
+    phis: Vec<Vec<(String, String, Vec<String>)>>,
+    blocks: Vec<Vec<IRInstr>>,                 // renamed instructions of each block
+}
+
+impl Renamer<'_> {
+    fn rename(&mut self, b: usize) {
+        let mut pushed: Vec<String> = Vec::new();
+
+        for i in 0..self.phis[b].len() {
+            let name = self.phis[b][i].0.clone();
+            self.phis[b][i].1 = self.define(&name, &mut pushed);
+        }
+
+        let (code, block) = (self.code, &self.cfg.blocks[b]);
+        for instr in &code[block.start..block.end] {
+            let mut instr = rename_uses(instr, |name| self.read(name));
+            if let Some(def) = def_mut(&mut instr) {
+                *def = self.define(&def.clone(), &mut pushed);
+            }
+            self.blocks[b].push(instr);
+        }
+
+        // fill in this block's slot in the phis of the blocks it goes to
+        // (a block can be there twice when both ways out of a JumpIfFalse go to it)
+        for succ in self.cfg.successors(b).collect::<HashSet<_>>() {
+            let reachable_preds = self.preds[succ].iter().filter(|&&p| self.idom[p].is_some());
+            let slots: Vec<usize> = reachable_preds.enumerate().filter(|(_, &p)| p == b).map(|(slot, _)| slot).collect();
+            for i in 0..self.phis[succ].len() {
+                let version = self.read(&self.phis[succ][i].0);
+                for &slot in &slots {
+                    self.phis[succ][i].2[slot] = version.clone();
+                }
+            }
+        }
+
+        for &child in &self.children[b] {
+            self.rename(child);
+        }
+
+        for name in pushed {
+            self.current.get_mut(&name).and_then(Vec::pop);
+        }
+    }
+
+    // the version a read of name sees here, the name itself before any write
+    fn read(&self, name: &str) -> String {
+        self.current.get(name).and_then(|stack| stack.last()).cloned().unwrap_or_else(|| name.to_string())
+    }
+
+    // a fresh version for a write to name, visible until this block's subtree is done
+    fn define(&mut self, name: &str, pushed: &mut Vec<String>) -> String {
+        let version = loop {
+            let n = self.versions.entry(name.to_string()).or_insert(0);
+            *n += 1;
+            let candidate = format!("{}_{}", name, n);
+            if self.taken.insert(candidate.clone()) {
+                break candidate;
+            }
+        };
+        self.current.entry(name.to_string()).or_default().push(version.clone());
+        pushed.push(name.to_string());
+        version
+    }
+}
+
+// the instruction with every name it reads passed through rename
+fn rename_uses(instr: &IRInstr, rename: impl Fn(&str) -> String) -> IRInstr {
+    let all = |names: &[String]| names.iter().map(|n| rename(n)).collect();
+    match instr {
+        IRInstr::Assign(target, IRValue::Var(src)) => IRInstr::Assign(target.clone(), IRValue::Var(rename(src))),
+        IRInstr::Assign(target, IRValue::Temp(src)) => IRInstr::Assign(target.clone(), IRValue::Temp(rename(src))),
+        IRInstr::BinaryOp(res, l, op, r) => IRInstr::BinaryOp(res.clone(), rename(l), op.clone(), rename(r)),
+        IRInstr::UnaryOp(res, op, operand) => IRInstr::UnaryOp(res.clone(), op.clone(), rename(operand)),
+        IRInstr::Call(res, name, args) => IRInstr::Call(res.clone(), name.clone(), all(args)),
+        IRInstr::MakeArray(res, elements) => IRInstr::MakeArray(res.clone(), all(elements)),
+        IRInstr::Index(res, base, index) => IRInstr::Index(res.clone(), rename(base), rename(index)),
+        IRInstr::Return(name) => IRInstr::Return(name.as_deref().map(&rename)),
+        IRInstr::JumpIfFalse(cond, label) => IRInstr::JumpIfFalse(rename(cond), label.clone()),
+        IRInstr::Phi(res, sources) => IRInstr::Phi(res.clone(), all(sources)),
+        IRInstr::Assign(..) | IRInstr::Label(_) | IRInstr::Jump(_) => instr.clone(),
+    }
+}
+
+fn def_mut(instr: &mut IRInstr) -> Option<&mut String> {
+    match instr {
+        IRInstr::Assign(target, _)
+        | IRInstr::BinaryOp(target, ..)
+        | IRInstr::UnaryOp(target, ..)
+        | IRInstr::Call(target, ..)
+        | IRInstr::MakeArray(target, _)
+        | IRInstr::Index(target, ..)
+        | IRInstr::Phi(target, _) => Some(target),
+        IRInstr::Return(_) | IRInstr::Label(_) | IRInstr::Jump(_) | IRInstr::JumpIfFalse(..) => None,
+    }
+}
+
+fn names(instr: &IRInstr) -> impl Iterator<Item = &str> {
+    let (def, uses) = instr.defs_and_uses();
+    def.into_iter().chain(uses).map(String::as_str)
+}
+
+// every name that's written, with the blocks that write it
+fn def_blocks<'a>(code: &'a [IRInstr], cfg: &Cfg) -> HashMap<&'a str, Vec<usize>> {
+    let mut defs: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (b, block) in cfg.blocks.iter().enumerate() {
+        for instr in &code[block.start..block.end] {
+            if let Some(def) = instr.defs_and_uses().0 {
+                defs.entry(def).or_default().push(b);
+            }
+        }
+    }
+    defs
+}
+
+// names each block may read before writing them, from it or a block after it
+fn live_in<'a>(code: &'a [IRInstr], cfg: &Cfg, preds: &[Vec<usize>]) -> Vec<HashSet<&'a str>> {
+    // what each block reads before writing, and what it writes
+    let (uses, defs): (Vec<HashSet<&str>>, Vec<HashSet<&str>>) = cfg
+        .blocks
+        .iter()
+        .map(|block| {
+            let mut uses = HashSet::new();
+            let mut defs = HashSet::new();
+            for instr in &code[block.start..block.end] {
+                let (def, read) = instr.defs_and_uses();
+                uses.extend(read.into_iter().map(String::as_str).filter(|name| !defs.contains(name)));
+                defs.extend(def.map(String::as_str));
+            }
+            (uses, defs)
+        })
+        .unzip();
+
+    let mut live = uses.clone();
+    let mut work: Vec<usize> = (0..cfg.blocks.len()).collect();
+    while let Some(b) = work.pop() {
+        // whatever b needs on the way in, its predecessors need too, unless they write it
+        let needed: Vec<&str> = live[b].iter().copied().collect();
+        for &p in &preds[b] {
+            let before = live[p].len();
+            live[p].extend(needed.iter().copied().filter(|name| !defs[p].contains(name)));
+            if live[p].len() != before {
+                work.push(p);
+            }
+        }
+    }
+    live
+}
+
+// reachable blocks, each one after everything that leads to it except along a loop's back edge
+fn reverse_postorder(cfg: &Cfg) -> Vec<usize> {
+    let mut visited = vec![false; cfg.blocks.len()];
+    let mut order = Vec::new();
+    // (block, successors already pushed)
+    let mut stack = vec![(0, false)];
+    while let Some((b, expanded)) = stack.pop() {
+        if expanded {
+            order.push(b);
+            continue;
+        }
+        if visited[b] {
+            continue;
+        }
+        visited[b] = true;
+        stack.push((b, true));
+        stack.extend(cfg.successors(b).filter(|&s| !visited[s]).map(|s| (s, false)));
+    }
+    order.reverse();
+    order
+}
+
+// the closest block every path from the entry to b goes through, None for the
+// entry itself and blocks that can't be reached. Cooper, Harvey and Kennedy's
+// "A Simple, Fast Dominance Algorithm"
+fn immediate_dominators(cfg: &Cfg, preds: &[Vec<usize>], order: &[usize]) -> Vec<Option<usize>> {
+    let mut rank = vec![usize::MAX; cfg.blocks.len()];
+    for (i, &b) in order.iter().enumerate() {
+        rank[b] = i;
+    }
+    let mut idom: Vec<Option<usize>> = vec![None; cfg.blocks.len()];
+    idom[0] = Some(0);
+
+    let intersect = |idom: &[Option<usize>], mut a: usize, mut b: usize| {
+        while a != b {
+            while rank[a] > rank[b] {
+                a = idom[a].expect("processed blocks have an idom");
+            }
+            while rank[b] > rank[a] {
+                b = idom[b].expect("processed blocks have an idom");
+            }
+        }
+        a
+    };
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &b in order.iter().skip(1) {
+            let mut processed = preds[b].iter().copied().filter(|&p| idom[p].is_some());
+            let Some(first) = processed.next() else { continue };
+            let new_idom = processed.fold(first, |acc, p| intersect(&idom, acc, p));
+            if idom[b] != Some(new_idom) {
+                idom[b] = Some(new_idom);
+                changed = true;
+            }
+        }
+    }
+    idom
+}
+
+// for each block, the join points where its dominance stops, which is where
+// a write in it needs a phi
+fn dominance_frontiers(preds: &[Vec<usize>], idom: &[Option<usize>]) -> Vec<HashSet<usize>> {
+    let mut frontiers = vec![HashSet::new(); preds.len()];
+    for (b, block_preds) in preds.iter().enumerate() {
+        let Some(b_idom) = idom[b] else { continue };
+        if block_preds.len() < 2 {
+            continue;
+        }
+        for &p in block_preds.iter().filter(|&&p| idom[p].is_some()) {
+            let mut runner = p;
+            while runner != b_idom {
+                frontiers[runner].insert(b);
+                let Some(next) = idom[runner] else { break };
+                // the entry is its own idom, stop there
+                if next == runner {
+                    break;
+                }
+                runner = next;
+            }
+        }
+    }
+    frontiers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // main's IR straight out of the generator
+    fn main_ir(src: &str) -> Vec<IRInstr> {
+        let tokens = crate::lex_layer::tokenize(src).unwrap();
+        let funcs = crate::parse(&tokens).unwrap();
+        crate::generate_ir(&funcs).into_iter().find(|f| f.name == "main").unwrap().code
+    }
+
+    fn assign(target: &str, value: IRValue) -> IRInstr {
+        IRInstr::Assign(target.to_string(), value)
+    }
+
+    #[test]
+    fn reassigned_variable_gets_new_versions() {
+        let code = vec![
+            assign("x", IRValue::Int(1)),
+            IRInstr::BinaryOp("x".to_string(), "x".to_string(), "+".to_string(), "y".to_string()),
+            IRInstr::Return(Some("x".to_string())),
+        ];
+        assert_eq!(
+            to_ssa(&code),
+            vec![
+                assign("x_1", IRValue::Int(1)),
+                IRInstr::BinaryOp("x_2".to_string(), "x_1".to_string(), "+".to_string(), "y".to_string()),
+                IRInstr::Return(Some("x_2".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn copies_read_the_latest_version() {
+        let ssa = to_ssa(&main_ir("func main() -> Int { var x = 1; x = x + 2; x = x * 3; return x; }"));
+        assert_eq!(ssa[3], assign("x_2", IRValue::Temp("%1_1".to_string())));
+        assert_eq!(ssa[6], assign("x_3", IRValue::Temp("%3_1".to_string())));
+        assert_eq!(ssa[7], IRInstr::Return(Some("x_3".to_string())));
+    }
+
+    #[test]
+    fn join_point_gets_a_phi() {
+        let ssa = to_ssa(&main_ir("func main() -> Int { var x = 1; if (x < 2) { x = 3; } else { x = 4; } return x; }"));
+        let phi = IRInstr::Phi("x_4".to_string(), vec!["x_2".to_string(), "x_3".to_string()]);
+        assert_eq!(ssa[ssa.len() - 2..], [phi, IRInstr::Return(Some("x_4".to_string()))]);
+    }
+
+    #[test]
+    fn every_name_is_written_once() {
+        let code = main_ir("func main() -> Int { var i = 0; while (i < 10) { i = i + 1; } return i; }");
+        let ssa = to_ssa(&code);
+        let mut written = HashSet::new();
+        for instr in &ssa {
+            if let Some(def) = instr.defs_and_uses().0 {
+                assert!(written.insert(def.clone()), "{} written twice in {:?}", def, ssa);
+            }
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use crate::lex_layer::{LiteralType, Token};
+use std::fmt;
+
+use crate::lex_layer::{LiteralType, SpannedToken, Token};
+use crate::semantic_analyzer::Type;
 //There is an error where it is expecting a delimeter but finds an identifier.
 //The fix will be made at a later day
 
@@ -6,81 +9,126 @@ use crate::lex_layer::{LiteralType, Token};
 #[derive(Debug)]
 pub struct Function {
     pub name: String,
-    pub params: Vec<String>,
-    pub body: Vec<Statement>,
+    pub params: Vec<(String, Type)>, // from `x: Int`
+    pub return_type: Option<Type>, // from `-> Int`, None when it returns nothing
+    pub body: Vec<SpannedStatement>,
+    pub line: usize, // where its name is, errors about the function as a whole point here
+    pub col: usize,
+}
+
+//a statement and the line and col of the token it starts with
+#[derive(Debug)]
+pub struct SpannedStatement {
+    pub stmt: Statement,
+    pub line: usize,
+    pub col: usize,
 }
 
 #[derive(Debug)]
 pub enum Statement {
-    VarDecl { name: String, value: Expression },
+    VarDecl { name: String, value: Expression, mutable: bool }, // mutable is false for `const`
+    Assign { name: String, value: Expression }, // x = value; on an existing var
     Expr(Expression),
-    Return(Expression),
+    Return(Option<Expression>), // None for a bare `return;`
+    If {
+        cond: Expression,
+        then_body: Vec<SpannedStatement>,
+        else_body: Option<Vec<SpannedStatement>>, // an `else if` is a single nested If
+    },
+    While { cond: Expression, body: Vec<SpannedStatement> },
+    Break, // leaves the innermost loop
+    Continue, // goes back to the innermost loop's condition
+    Block(Vec<SpannedStatement>), // { ... } on its own, vars declared in it end with it
 }
 
 
 #[derive(Debug)]
 pub enum Expression {
     Integer(i64),
+    Float(f64),
     Boolean(bool),
     String(String),
+    Char(char),
     Ident(String),
     BinaryOp {
         left: Box<Expression>,
         op: String,
         right: Box<Expression>,
     },
+    Call {
+        name: String,
+        args: Vec<Expression>,
+    },
+    Unary {
+        op: String, // "-" or "!"
+        operand: Box<Expression>,
+    },
+    Array(Vec<Expression>), // [a, b, c]
+    Index {
+        base: Box<Expression>,
+        index: Box<Expression>,
+    },
+}
+
+//a parse failure and the line and col of the token it stopped at, the
+//message says where too so it reads on its own
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub msg: String,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl ParseError {
+    fn new((line, col): (usize, usize), msg: String) -> Self {
+        Self { msg, line, col }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+//what a single REPL line turned out to be
+#[derive(Debug)]
+pub enum ReplLine {
+    Expr(Expression), // a bare expression with no ';' after it, its value gets shown
+    Statements(Vec<SpannedStatement>),
 }
 //AST types end
 
 
 //Parser Struct start
 pub struct Parser<'a> {
-    tokens: &'a [Token],
+    tokens: &'a [SpannedToken],
     current: usize,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(tokens: &'a [Token]) -> Self {
+    pub fn new(tokens: &'a [SpannedToken]) -> Self {
         Self { tokens, current: 0 }
     }
 
-    fn peek_raw(&self) -> Option<&Token> {
-        self.tokens.get(self.current)
-    }
-
-    fn advance_raw(&mut self) -> Option<&Token> {
-        let tok = self.tokens.get(self.current);
-        if tok.is_some() {
-            self.current += 1;
-        }
-        tok
-    }
-
-    fn expect_keyword(&mut self, kw: &str) -> Result<(), String> {
+    fn expect_keyword(&mut self, kw: &str) -> Result<(), ParseError> {
+        let pos = self.pos();
         match self.advance() {
             Some(Token::Keyword(s)) if s == kw => Ok(()),
-            other => Err(format!("Expected keyword '{}', found {:?}", kw, other)),
+            other => {
+                Err(ParseError::new(pos, format!("Expected keyword '{}' at {}, found {}", kw, at(pos), describe(other))))
+            }
         }
     }
 
-    fn expect_operator(&mut self, op: &str) -> Result<(), String> {
+    fn expect_operator(&mut self, op: &str) -> Result<(), ParseError> {
+        let pos = self.pos();
         match self.advance() {
             Some(Token::Operator(s)) if s == op => Ok(()),
-            other => Err(format!("Expected operator '{}', found {:?}", op, other)),
-        }
-    }
-    fn expect_delim_raw(&mut self, ch: char) -> Result<(), String> {
-        while let Some(tok) = self.tokens.get(self.current) {
-            match tok {
-                Token::Whitespace | Token::Comment => self.current += 1, // skip
-                Token::Delimiter(c) if *c == ch => {
-                    self.current += 1;
-                    return Ok(());
-                }
-                other => return Err(format!("Expected delimiter '{}', found {:?}", ch, other)),
+            other => {
+                Err(ParseError::new(pos, format!("Expected operator '{}' at {}, found {}", op, at(pos), describe(other))))
             }
         }
-        Err(format!("Expected delimiter '{}', found end of input", ch))
     }
 }
 //Parser struct end
@@ -88,126 +136,281 @@ impl<'a> Parser<'a> {
 
 //Parse a func start
 impl<'a> Parser<'a> {
-    pub fn parse_function(&mut self) -> Result<Function, String> {
+    //parses every function in the file until only whitespace/comments are left
+    pub fn parse_program(&mut self) -> Result<Vec<Function>, ParseError> {
+        let mut funcs = Vec::new();
+        while !matches!(self.peek(), None | Some(Token::Eof)) {
+            funcs.push(self.parse_function()?);
+        }
+        Ok(funcs)
+    }
+
+    //a line typed at a REPL, either one bare expression like `40 + 2` or statements
+    pub fn parse_repl_line(&mut self) -> Result<ReplLine, ParseError> {
+        let start = self.current;
+        if let Ok(expr) = self.parse_expression() {
+            if matches!(self.peek(), None | Some(Token::Eof)) {
+                return Ok(ReplLine::Expr(expr));
+            }
+        }
+
+        //not a lone expression, go back and read it as statements instead
+        self.current = start;
+        let stmts = self.parse_statements()?;
+        let pos = self.pos();
+        match self.peek() {
+            None | Some(Token::Eof) => Ok(ReplLine::Statements(stmts)),
+            other => Err(ParseError::new(pos, format!("Unexpected {} at {}", describe(other), at(pos)))),
+        }
+    }
+
+    pub fn parse_function(&mut self) -> Result<Function, ParseError> {
         self.expect_keyword("func")?;
 
+        let pos = self.pos();
+        let (line, col) = pos;
         let name = match self.advance() {
             Some(Token::Identifier(s)) => s.clone(),
-            other => return Err(format!("Expected function name, found {:?}", other)),
+            other => {
+                return Err(ParseError::new(pos, format!("Expected function name at {}, found {}", at(pos), describe(other))))
+            }
         };
 
         self.expect_delim('(')?;
         let mut params = Vec::new();
-        loop {
-            match self.peek() {
-                Some(Token::Identifier(s)) => {
-                    params.push(s.clone());
-                    self.advance();
-                    if let Some(Token::Delimiter(',')) = self.peek() {
+        if self.peek() == Some(&Token::Delimiter(')')) {
+            self.advance();
+        } else {
+            //params are separated by commas, with none after the last one
+            loop {
+                let pos = self.pos();
+                let name = match self.advance() {
+                    Some(Token::Identifier(s)) => s.clone(),
+                    other => {
+                        return Err(ParseError::new(
+                            pos,
+                            format!("Unexpected token in parameters at {}: {}", at(pos), describe(other)),
+                        ));
+                    }
+                };
+                self.expect_delim(':')?;
+                let ty = self.parse_type()?;
+                params.push((name, ty));
+                match self.peek() {
+                    Some(Token::Delimiter(',')) => {
                         self.advance();
                     }
+                    _ => {
+                        self.expect_delim(')')?;
+                        break;
+                    }
                 }
-                Some(Token::Delimiter(')')) => {
-                    self.advance();
-                    break;
-                }
-                other => return Err(format!("Unexpected token in parameters: {:?}", other)),
             }
         }
 
+        let return_type = match self.peek() {
+            Some(Token::Operator(op)) if op == "->" => {
+                self.advance();
+                Some(self.parse_type()?)
+            }
+            _ => None,
+        };
+
         self.expect_delim('{')?;
         let body = self.parse_statements()?;
         self.expect_delim('}')?;
 
-        Ok(Function { name, params, body })
+        Ok(Function { name, params, return_type, body, line, col })
+    }
+
+    //type names used in annotations
+    fn parse_type(&mut self) -> Result<Type, ParseError> {
+        let pos = self.pos();
+        match self.advance() {
+            Some(Token::Identifier(s)) if s == "Int" => Ok(Type::Int),
+            Some(Token::Identifier(s)) if s == "Float" => Ok(Type::Float),
+            Some(Token::Identifier(s)) if s == "Bool" => Ok(Type::Bool),
+            Some(Token::Identifier(s)) if s == "Str" => Ok(Type::Str),
+            Some(Token::Identifier(s)) if s == "Char" => Ok(Type::Char),
+            //[Int] is an array of Int
+            Some(Token::Delimiter('[')) => {
+                let elem = self.parse_type()?;
+                self.expect_delim(']')?;
+                Ok(Type::Array(Box::new(elem)))
+            }
+            other => Err(ParseError::new(pos, format!("Expected type name at {}, found {}", at(pos), describe(other)))),
+        }
     }
 }
 //Parse a func end
 
 //parse statements start
 impl<'a> Parser<'a> {
-    fn parse_statements(&mut self) -> Result<Vec<Statement>, String> {
+    fn parse_statements(&mut self) -> Result<Vec<SpannedStatement>, ParseError> {
         let mut stmts = Vec::new();
         while let Some(tok) = self.peek() {
-            match tok {
-                Token::Keyword(s) if s == "var" => stmts.push(self.parse_var_decl()?),
-                Token::Keyword(s) if s == "return" => stmts.push(self.parse_return()?),
-                Token::Delimiter('}') => break,
+            let (line, col) = self.pos();
+            let stmt = match tok {
+                Token::Keyword(s) if s == "var" || s == "const" => self.parse_var_decl()?,
+                Token::Keyword(s) if s == "return" => self.parse_return()?,
+                Token::Keyword(s) if s == "if" => self.parse_if()?,
+                Token::Keyword(s) if s == "while" => self.parse_while()?,
+                Token::Keyword(s) if s == "break" || s == "continue" => {
+                    let stmt = if s == "break" { Statement::Break } else { Statement::Continue };
+                    self.advance();
+                    self.expect_delim(';')?;
+                    stmt
+                }
+                Token::Delimiter('{') => {
+                    self.advance();
+                    let body = self.parse_statements()?;
+                    self.expect_delim('}')?;
+                    Statement::Block(body)
+                }
+                Token::Identifier(_) if self.peek_second() == Some(&Token::Operator("=".to_string())) => {
+                    self.parse_assign()?
+                }
+                Token::Delimiter('}') | Token::Eof => break,
                 _ => {
                     let expr = self.parse_expression()?;
                     self.expect_delim(';')?;
-                    stmts.push(Statement::Expr(expr));
+                    Statement::Expr(expr)
                 }
-            }
+            };
+            stmts.push(SpannedStatement { stmt, line, col });
         }
         Ok(stmts)
     }
 
 
-    fn parse_var_decl(&mut self) -> Result<Statement, String> {
-        self.expect_keyword("var")?;
+    //var x = ...; or const x = ...; for one that can't be assigned to again
+    fn parse_var_decl(&mut self) -> Result<Statement, ParseError> {
+        let keyword = if self.peek() == Some(&Token::Keyword("const".to_string())) { "const" } else { "var" };
+        self.expect_keyword(keyword)?;
 
+        let pos = self.pos();
         let name = match self.advance() {
             Some(Token::Identifier(s)) => s.clone(),
-            other => return Err(format!("Expected identifier after 'var', found {:?}", other)),
+            other => {
+                return Err(ParseError::new(
+                    pos,
+                    format!("Expected identifier after '{}' at {}, found {}", keyword, at(pos), describe(other)),
+                ))
+            }
         };
 
         self.expect_operator("=")?;
         let value = self.parse_expression()?;  // now stops before semicolon
         self.expect_delim(';')?;               // correctly consumes the semicolon
 
-        Ok(Statement::VarDecl { name, value })
+        Ok(Statement::VarDecl { name, value, mutable: keyword == "var" })
+    }
+
+    fn parse_assign(&mut self) -> Result<Statement, ParseError> {
+        let pos = self.pos();
+        let name = match self.advance() {
+            Some(Token::Identifier(s)) => s.clone(),
+            other => {
+                return Err(ParseError::new(pos, format!("Expected identifier at {}, found {}", at(pos), describe(other))))
+            }
+        };
+
+        self.expect_operator("=")?;
+        let value = self.parse_expression()?;
+        self.expect_delim(';')?;
+
+        Ok(Statement::Assign { name, value })
     }
 
-    fn parse_return(&mut self) -> Result<Statement, String> {
+    fn parse_return(&mut self) -> Result<Statement, ParseError> {
         self.expect_keyword("return")?;
-        let value = self.parse_expression()?;  // stops before semicolon
+        let value = match self.peek() {
+            Some(Token::Delimiter(';')) => None,
+            _ => Some(self.parse_expression()?), // stops before semicolon
+        };
         self.expect_delim(';')?;               // consumes the ';'
         Ok(Statement::Return(value))
     }
 
+    //if (cond) { ... } with optional else { ... } or else if ...
+    fn parse_if(&mut self) -> Result<Statement, ParseError> {
+        self.expect_keyword("if")?;
+        self.expect_delim('(')?;
+        let cond = self.parse_expression()?;
+        self.expect_delim(')')?;
+
+        self.expect_delim('{')?;
+        let then_body = self.parse_statements()?;
+        self.expect_delim('}')?;
+
+        let else_body = match self.peek() {
+            Some(Token::Keyword(s)) if s == "else" => {
+                self.advance();
+                //else if chains without extra braces, the inner if becomes the whole else body
+                match self.peek() {
+                    Some(Token::Keyword(s)) if s == "if" => {
+                        let (line, col) = self.pos();
+                        Some(vec![SpannedStatement { stmt: self.parse_if()?, line, col }])
+                    }
+                    Some(Token::Delimiter('{')) => {
+                        self.advance();
+                        let body = self.parse_statements()?;
+                        self.expect_delim('}')?;
+                        Some(body)
+                    }
+                    other => {
+                        let pos = self.pos();
+                        return Err(ParseError::new(
+                            pos,
+                            format!("Expected '{{' or 'if' after 'else' at {}, found {}", at(pos), describe(other)),
+                        ));
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        Ok(Statement::If { cond, then_body, else_body })
+    }
+
+    //while (cond) { ... }
+    fn parse_while(&mut self) -> Result<Statement, ParseError> {
+        self.expect_keyword("while")?;
+        self.expect_delim('(')?;
+        let cond = self.parse_expression()?;
+        self.expect_delim(')')?;
+
+        self.expect_delim('{')?;
+        let body = self.parse_statements()?;
+        self.expect_delim('}')?;
+
+        Ok(Statement::While { cond, body })
+    }
+
 }
 //parse statements end
 
 //parse expressions start
 impl<'a> Parser<'a> {
-    fn parse_expression(&mut self) -> Result<Expression, String> {
-        // left-hand side
-        let mut left = match self.advance() {
-            Some(Token::Literal(LiteralType::Integer(n))) => Expression::Integer(*n),
-            Some(Token::Literal(LiteralType::Boolean(b))) => Expression::Boolean(*b),
-            Some(Token::Literal(LiteralType::String(s))) => Expression::String(s.clone()),
-            Some(Token::Identifier(s)) => Expression::Ident(s.clone()),
-
-            // handle grouped expressions like (x + y)
-            Some(Token::Delimiter('(')) => {
-                let expr = self.parse_expression()?;
-                self.expect_delim(')')?;
-                expr
-            }
+    fn parse_expression(&mut self) -> Result<Expression, ParseError> {
+        self.parse_binary(1)
+    }
 
-            other => return Err(format!("Unexpected token in expression: {:?}", other)),
-        };
+    //precedence climbing: only operators binding at least as tight as min_prec are
+    //taken here, the right side is parsed one level tighter so same-level ops
+    //end up left-associative (2 - 3 - 4 is (2 - 3) - 4)
+    fn parse_binary(&mut self, min_prec: u8) -> Result<Expression, ParseError> {
+        let mut left = self.parse_unary()?;
 
-        // left-associative loop
         while let Some(Token::Operator(op)) = self.peek() {
+            let prec = match precedence(op) {
+                Some(p) if p >= min_prec => p,
+                _ => break,
+            };
             let op_str = op.clone();
             self.advance(); // consume operator
 
-            // parse *next primary*, not full expression (so it doesn't recurse infinitely)
-            let mut right = match self.advance() {
-                Some(Token::Literal(LiteralType::Integer(n))) => Expression::Integer(*n),
-                Some(Token::Literal(LiteralType::Boolean(b))) => Expression::Boolean(*b),
-                Some(Token::Literal(LiteralType::String(s))) => Expression::String(s.clone()),
-                Some(Token::Identifier(s)) => Expression::Ident(s.clone()),
-                Some(Token::Delimiter('(')) => {
-                    let expr = self.parse_expression()?;
-                    self.expect_delim(')')?;
-                    expr
-                }
-                other => return Err(format!("Unexpected token after operator: {:?}", other)),
-            };
-
+            let right = self.parse_binary(prec + 1)?;
             left = Expression::BinaryOp {
                 left: Box::new(left),
                 op: op_str,
@@ -218,41 +421,275 @@ impl<'a> Parser<'a> {
         Ok(left)
     }
 
+    //a leading - or ! applies to just the next operand, so -a * b is (-a) * b.
+    //only reached where an operand is expected, a - between operands is binary
+    fn parse_unary(&mut self) -> Result<Expression, ParseError> {
+        match self.peek() {
+            Some(Token::Operator(op)) if op == "-" || op == "!" => {
+                let op = op.clone();
+                self.advance();
+                let operand = self.parse_unary()?;
+                Ok(Expression::Unary { op, operand: Box::new(operand) })
+            }
+            _ => self.parse_postfix(),
+        }
+    }
 
+    //indexing binds tighter than anything else and chains, a[0][1] is (a[0])[1]
+    fn parse_postfix(&mut self) -> Result<Expression, ParseError> {
+        let mut expr = self.parse_primary()?;
+        while self.peek() == Some(&Token::Delimiter('[')) {
+            self.advance();
+            let index = self.parse_expression()?;
+            self.expect_delim(']')?;
+            expr = Expression::Index { base: Box::new(expr), index: Box::new(index) };
+        }
+        Ok(expr)
+    }
 
+    //literals, identifiers and grouped expressions
+    fn parse_primary(&mut self) -> Result<Expression, ParseError> {
+        let pos = self.pos();
+        match self.advance() {
+            Some(Token::Literal(LiteralType::Integer(n))) => Ok(Expression::Integer(*n)),
+            Some(Token::Literal(LiteralType::Float(n))) => Ok(Expression::Float(*n)),
+            Some(Token::Literal(LiteralType::Boolean(b))) => Ok(Expression::Boolean(*b)),
+            Some(Token::Literal(LiteralType::String(s))) => Ok(Expression::String(s.clone())),
+            Some(Token::Literal(LiteralType::Char(c))) => Ok(Expression::Char(*c)),
+            //an identifier right before '(' is a call like foo(a, b)
+            Some(Token::Identifier(s)) if self.peek() == Some(&Token::Delimiter('(')) => {
+                self.advance();
+                let args = self.parse_list(')')?;
+                Ok(Expression::Call { name: s.clone(), args })
+            }
+            Some(Token::Identifier(s)) => Ok(Expression::Ident(s.clone())),
+
+            // handle grouped expressions like (x + y)
+            Some(Token::Delimiter('(')) => {
+                let expr = self.parse_expression()?;
+                self.expect_delim(')')?;
+                Ok(expr)
+            }
+
+            Some(Token::Delimiter('[')) => Ok(Expression::Array(self.parse_list(']')?)),
+
+            other => {
+                Err(ParseError::new(pos, format!("Unexpected token in expression at {}: {}", at(pos), describe(other))))
+            }
+        }
+    }
+
+    //comma separated expressions up to `close`, like call arguments or array
+    //elements, the opening delimiter is already consumed
+    fn parse_list(&mut self, close: char) -> Result<Vec<Expression>, ParseError> {
+        let mut args = Vec::new();
+        if self.peek() == Some(&Token::Delimiter(close)) {
+            self.advance();
+            return Ok(args);
+        }
+        loop {
+            args.push(self.parse_expression()?);
+            match self.peek() {
+                Some(Token::Delimiter(',')) => {
+                    self.advance();
+                }
+                _ => {
+                    self.expect_delim(close)?;
+                    return Ok(args);
+                }
+            }
+        }
+    }
+}
+
+//how tightly a binary operator binds, higher binds tighter.
+//None means the operator can't appear between two expressions
+fn precedence(op: &str) -> Option<u8> {
+    match op {
+        "||" => Some(1),
+        "&&" => Some(2),
+        "==" | "!=" => Some(3),
+        "<" | ">" | "<=" | ">=" => Some(4),
+        "+" | "-" => Some(5),
+        "*" | "/" | "%" => Some(6),
+        _ => None,
+    }
 }
 //parse expression end
 
 
 //parse ignore whitespace start
 impl<'a> Parser<'a> {
-    fn advance(&mut self) -> Option<&Token> {
+    //Eof is never consumed, so everything after the end keeps seeing it
+    fn advance(&mut self) -> Option<&'a Token> {
         while let Some(tok) = self.tokens.get(self.current) {
+            if tok.token == Token::Eof {
+                return Some(&tok.token);
+            }
             self.current += 1;
-            if matches!(tok, Token::Whitespace | Token::Comment) {
+            if matches!(tok.token, Token::Whitespace | Token::Comment(_)) {
                 continue;
             }
-            return Some(tok);
+            return Some(&tok.token);
         }
         None
     }
 
-    fn peek(&self) -> Option<&Token> {
+    fn peek(&self) -> Option<&'a Token> {
+        self.peek_spanned().map(|tok| &tok.token)
+    }
+
+    //the real token after the one peek() returns
+    fn peek_second(&self) -> Option<&'a Token> {
+        self.tokens[self.current..]
+            .iter()
+            .filter(|tok| !matches!(tok.token, Token::Whitespace | Token::Comment(_)))
+            .nth(1)
+            .map(|tok| &tok.token)
+    }
+
+    fn peek_spanned(&self) -> Option<&'a SpannedToken> {
         self.tokens[self.current..]
             .iter()
-            .find(|tok| !matches!(tok, Token::Whitespace | Token::Comment))
+            .find(|tok| !matches!(tok.token, Token::Whitespace | Token::Comment(_)))
+    }
+
+    //line and column of the next real token. Eof always has one, token lists
+    //that don't end in it point past their last token
+    fn pos(&self) -> (usize, usize) {
+        match (self.peek_spanned(), self.tokens.last()) {
+            (Some(tok), _) | (None, Some(tok)) => (tok.line, tok.col),
+            (None, None) => (1, 1),
+        }
     }
 
-    fn expect_delim(&mut self, ch: char) -> Result<(), String> {
+    fn expect_delim(&mut self, ch: char) -> Result<(), ParseError> {
         while let Some(tok) = self.tokens.get(self.current) {
-            match tok {
-                Token::Whitespace | Token::Comment => { self.current += 1; continue; }
+            match &tok.token {
+                Token::Whitespace | Token::Comment(_) => { self.current += 1; continue; }
                 Token::Delimiter(c) if *c == ch => { self.current += 1; return Ok(()); }
-                other => return Err(format!("Expected delimiter '{}', found {:?}", ch, other)),
+                other => {
+                    return Err(ParseError::new(
+                        (tok.line, tok.col),
+                        format!(
+                            "Expected delimiter '{}' at line {}, col {}, found {}",
+                            ch, tok.line, tok.col, describe(Some(other))
+                        ),
+                    ))
+                }
             }
         }
-        Err(format!("Expected delimiter '{}', found end of input", ch))
+        Err(ParseError::new(self.pos(), format!("Expected delimiter '{}', found end of file", ch)))
     }
 
 }
+
+//renders a position from pos() for error messages
+fn at((line, col): (usize, usize)) -> String {
+    format!("line {}, col {}", line, col)
+}
+
+//renders the token an error found, the end of the file reads as such rather than Eof/None
+fn describe(tok: Option<&Token>) -> String {
+    match tok {
+        None | Some(Token::Eof) => "end of file".to_string(),
+        Some(tok) => format!("{:?}", tok),
+    }
+}
 //parse ignore whitespace end
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lex_layer::tokenize;
+
+    fn parse_fn(src: &str) -> Function {
+        let tokens = tokenize(src).unwrap();
+        Parser::new(&tokens).parse_function().unwrap()
+    }
+
+    fn parse_expr(src: &str) -> Expression {
+        let tokens = tokenize(src).unwrap();
+        match Parser::new(&tokens).parse_repl_line().unwrap() {
+            ReplLine::Expr(expr) => expr,
+            other => panic!("expected an expression, got {:?}", other),
+        }
+    }
+
+    //fully parenthesized form of an expression, so a test can state its shape in one line
+    fn shape(expr: &Expression) -> String {
+        match expr {
+            Expression::Integer(n) => n.to_string(),
+            Expression::Ident(name) => name.clone(),
+            Expression::BinaryOp { left, op, right } => format!("({} {} {})", shape(left), op, shape(right)),
+            Expression::Unary { op, operand } => format!("({}{})", op, shape(operand)),
+            other => format!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn two_parameter_function() {
+        let func = parse_fn("func add(a: Int, b: Int) -> Int { return a + b; }");
+        assert_eq!(func.name, "add");
+        assert_eq!(func.params, vec![("a".to_string(), Type::Int), ("b".to_string(), Type::Int)]);
+        assert_eq!(func.return_type, Some(Type::Int));
+        assert_eq!(func.body.len(), 1);
+    }
+
+    #[test]
+    fn parameters_need_commas_between_and_none_after() {
+        for src in ["func f(a: Int b: Int) { }", "func f(a: Int, b: Int,) { }", "func f(,) { }"] {
+            let tokens = tokenize(src).unwrap();
+            assert!(Parser::new(&tokens).parse_function().is_err(), "{} should not parse", src);
+        }
+        assert!(parse_fn("func f() { }").params.is_empty());
+    }
+
+    #[test]
+    fn precedence_and_left_associativity() {
+        assert_eq!(shape(&parse_expr("2 + 3 * 4")), "(2 + (3 * 4))");
+        assert_eq!(shape(&parse_expr("2 * 3 + 4")), "((2 * 3) + 4)");
+        assert_eq!(shape(&parse_expr("2 - 3 - 4")), "((2 - 3) - 4)");
+        assert_eq!(shape(&parse_expr("8 / 4 / 2")), "((8 / 4) / 2)");
+        assert_eq!(shape(&parse_expr("(2 - 3) * 4")), "((2 - 3) * 4)");
+        assert_eq!(shape(&parse_expr("a + 1 < b * 2")), "((a + 1) < (b * 2))");
+        assert_eq!(shape(&parse_expr("a < b == c")), "((a < b) == c)");
+    }
+
+    #[test]
+    fn leading_minus_is_unary() {
+        assert_eq!(shape(&parse_expr("-5 - 2")), "((-5) - 2)");
+        assert_eq!(shape(&parse_expr("-a * b")), "((-a) * b)");
+        assert_eq!(shape(&parse_expr("!!a")), "(!(!a))");
+    }
+
+    #[test]
+    fn else_if_nests_in_the_else_branch() {
+        let func = parse_fn("func f() { if (a) { } else if (b) { } else { return; } }");
+        let Statement::If { else_body: Some(else_body), .. } = &func.body[0].stmt else {
+            panic!("expected if/else, got {:?}", func.body[0].stmt);
+        };
+        assert_eq!(else_body.len(), 1);
+        let Statement::If { cond, else_body: Some(last), .. } = &else_body[0].stmt else {
+            panic!("expected a nested if/else, got {:?}", else_body[0].stmt);
+        };
+        assert_eq!(shape(cond), "b");
+        assert!(matches!(last[..], [SpannedStatement { stmt: Statement::Return(None), .. }]));
+    }
+
+    #[test]
+    fn bare_braces_make_a_block() {
+        let func = parse_fn("func f() { { var x = 1; } }");
+        assert!(matches!(&func.body[0].stmt, Statement::Block(inner) if inner.len() == 1));
+    }
+
+    #[test]
+    fn break_and_continue_statements() {
+        let func = parse_fn("func f() { while (yeah) { continue; break; } }");
+        let Statement::While { body, .. } = &func.body[0].stmt else {
+            panic!("expected a while loop, got {:?}", func.body[0].stmt);
+        };
+        assert!(matches!(body[0].stmt, Statement::Continue));
+        assert!(matches!(body[1].stmt, Statement::Break));
+    }
+}
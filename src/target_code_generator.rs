@@ -1,49 +1,155 @@
 // target_code_generator.rs
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 
-use crate::intermediate_code_generator::{IRInstr, IRValue}; // adjust path if needed
+use crate::const_eval::repeat;
+use crate::intermediate_code_generator::{is_temp_name, IRFunction, IRInstr, IRValue}; // adjust path if needed
+use crate::semantic_analyzer::Type;
 
 // ===== VM instruction set (your existing opcodes, unchanged) =====
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum VMInstr {
     PushInt(i64),
     PushBool(bool),
     PushStr(String),
+    PushChar(char),
     Load(String),   // push variable value onto stack
     Store(String),  // pop stack, store into variable
     Add,
     Sub,
     Mul,
     Div,
+    Mod,    // remainder, same sign as the dividend like Rust's %, so -7 % 3 is -1
     Concat, // string concatenation
+    Eq,     // pop two values, push whether they are equal
+    Ne,     // pop two values, push whether they differ
     Ret,    // return with top-of-stack
     Jump(usize),             // unconditional jump to instruction index
     JumpIfFalse(usize),      // jump if top of stack is false
+    Call(usize, usize),      // call function at instruction index with this many args on the stack
+    Print,                   // pop a value and append it to the VM's output
+    Dup,                     // push a copy of the top of stack
+    Neg,                     // pop a number, push its negation
+    Not,                     // pop a boolean, push its opposite
+    Lt,                      // pop two integers, floats or strings, push whether the first is smaller
+    Gt,
+    Le,
+    Ge,
+    MakeArray(usize),        // pop this many values, push an array of them, first popped goes last
+    Index,                   // pop an index then an array, push that element
+    PushFloat(f64),
+    FAdd,                    // float versions of Add, Sub, Mul and Div, both operands must be floats
+    FSub,
+    FMul,
+    FDiv,                    // dividing by 0.0 gives inf or NaN like Rust's f64, not an error
+    IntToFloat,              // pop an integer, push it as a float
+    Pop,                     // discard the top of stack, for a value nothing reads
+    Repeat,                  // pop a count then a string, push the string that many times over
+
+}
 
+// name of every opcode, indexed by VMInstr::opcode
+const OPCODE_NAMES: [&str; 36] = [
+    "PushInt", "PushBool", "PushStr", "Load", "Store", "Add", "Sub", "Mul", "Div", "Concat",
+    "Eq", "Ne", "Ret", "Jump", "JumpIfFalse", "Call", "Print", "Dup", "Neg", "Not",
+    "Lt", "Gt", "Le", "Ge", "Mod", "PushChar", "MakeArray",
+    "Index", "PushFloat", "FAdd", "FSub", "FMul", "FDiv", "IntToFloat",
+    "Pop", "Repeat",
+];
+
+impl VMInstr {
+    // position of the variant in OPCODE_NAMES, used to tally executed instructions
+    fn opcode(&self) -> usize {
+        match self {
+            VMInstr::PushInt(_) => 0,
+            VMInstr::PushBool(_) => 1,
+            VMInstr::PushStr(_) => 2,
+            VMInstr::Load(_) => 3,
+            VMInstr::Store(_) => 4,
+            VMInstr::Add => 5,
+            VMInstr::Sub => 6,
+            VMInstr::Mul => 7,
+            VMInstr::Div => 8,
+            VMInstr::Concat => 9,
+            VMInstr::Eq => 10,
+            VMInstr::Ne => 11,
+            VMInstr::Ret => 12,
+            VMInstr::Jump(_) => 13,
+            VMInstr::JumpIfFalse(_) => 14,
+            VMInstr::Call(_, _) => 15,
+            VMInstr::Print => 16,
+            VMInstr::Dup => 17,
+            VMInstr::Neg => 18,
+            VMInstr::Not => 19,
+            VMInstr::Lt => 20,
+            VMInstr::Gt => 21,
+            VMInstr::Le => 22,
+            VMInstr::Ge => 23,
+            VMInstr::Mod => 24,
+            VMInstr::PushChar(_) => 25,
+            VMInstr::MakeArray(_) => 26,
+            VMInstr::Index => 27,
+            VMInstr::PushFloat(_) => 28,
+            VMInstr::FAdd => 29,
+            VMInstr::FSub => 30,
+            VMInstr::FMul => 31,
+            VMInstr::FDiv => 32,
+            VMInstr::IntToFloat => 33,
+            VMInstr::Pop => 34,
+            VMInstr::Repeat => 35,
+        }
+    }
 }
 
 // ===== runtime values on the VM stack =====
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum VMValue {
     Int(i64),
+    Float(f64),
     Bool(bool),
     Str(String),
+    Char(char),
+    Array(Vec<VMValue>),
+}
+
+// how a value looks when printed, strings without their quotes
+impl fmt::Display for VMValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VMValue::Int(n) => write!(f, "{}", n),
+            //Debug keeps the .0 on whole floats so 5.0 doesn't look like the Int 5
+            VMValue::Float(n) => write!(f, "{:?}", n),
+            VMValue::Bool(b) => write!(f, "{}", b),
+            VMValue::Str(s) => write!(f, "{}", s),
+            VMValue::Char(c) => write!(f, "{}", c),
+            VMValue::Array(elements) => {
+                write!(f, "[")?;
+                for (i, elem) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", elem)?;
+                }
+                write!(f, "]")
+            }
+        }
+    }
 }
 
 // ===== a call frame =====
-// each frame owns its own local variables map.
-// for now we keep it simple: no return-ip / caller state because
-// we are executing a single top-level function body. When adding calls,
-// you'll add return_ip / caller stacks here.
+// each frame owns its own local variables map and remembers where
+// to continue in the caller once the function returns.
 #[derive(Debug, Clone)]
 pub struct Frame {
     pub locals: std::collections::HashMap<String, VMValue>,
+    pub return_ip: usize,
 }
 
 impl Frame {
-    pub fn new() -> Self {
+    pub fn new(return_ip: usize) -> Self {
         Self {
             locals: std::collections::HashMap::new(),
+            return_ip,
         }
     }
 }
@@ -52,35 +158,366 @@ impl Frame {
 #[derive(Debug, Clone)]
 pub struct VMProgram {
     pub instrs: Vec<VMInstr>,
+    pub functions: Vec<FunctionInfo>, // every function in the program, sorted by entry
+}
+
+// what a Call needs to know about the function it jumps to
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub entry: usize,        // index of its first instruction
+    pub params: Vec<String>, // the arguments are stored under these names in the new frame
+}
+
+impl VMProgram {
+    // the function whose body starts at this index
+    pub fn function_at(&self, entry: usize) -> Option<&FunctionInfo> {
+        let i = self.functions.binary_search_by_key(&entry, |func| func.entry).ok()?;
+        Some(&self.functions[i])
+    }
+}
+
+// ===== bytecode files =====
+// the file starts with BYTECODE_MAGIC and the function table (a u32 count,
+// then each function's name, entry and u32 param count followed by the param
+// names), then every instruction is its opcode byte followed by its operands:
+// integers as 8 byte little endian, bools as one byte, strings as a u32 length
+// then the utf8 bytes
+const BYTECODE_MAGIC: &[u8; 4] = b"RCBC";
+
+impl VMProgram {
+    /// Encode the program so it can be saved and run later without recompiling
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = BYTECODE_MAGIC.to_vec();
+        let write_str = |out: &mut Vec<u8>, s: &str| {
+            out.extend_from_slice(&(s.len() as u32).to_le_bytes());
+            out.extend_from_slice(s.as_bytes());
+        };
+        out.extend_from_slice(&(self.functions.len() as u32).to_le_bytes());
+        for func in &self.functions {
+            write_str(&mut out, &func.name);
+            out.extend_from_slice(&(func.entry as u64).to_le_bytes());
+            out.extend_from_slice(&(func.params.len() as u32).to_le_bytes());
+            for param in &func.params {
+                write_str(&mut out, param);
+            }
+        }
+        for instr in &self.instrs {
+            out.push(instr.opcode() as u8);
+            match instr {
+                VMInstr::PushInt(n) => out.extend_from_slice(&n.to_le_bytes()),
+                VMInstr::PushFloat(n) => out.extend_from_slice(&n.to_bits().to_le_bytes()),
+                VMInstr::PushBool(b) => out.push(*b as u8),
+                VMInstr::PushChar(c) => out.extend_from_slice(&(*c as u32).to_le_bytes()),
+                VMInstr::PushStr(s) | VMInstr::Load(s) | VMInstr::Store(s) => write_str(&mut out, s),
+                VMInstr::Jump(n) | VMInstr::JumpIfFalse(n) | VMInstr::MakeArray(n) => {
+                    out.extend_from_slice(&(*n as u64).to_le_bytes());
+                }
+                VMInstr::Call(target, argc) => {
+                    out.extend_from_slice(&(*target as u64).to_le_bytes());
+                    out.extend_from_slice(&(*argc as u64).to_le_bytes());
+                }
+                _ => {}
+            }
+        }
+        out
+    }
+
+    /// Decode a program written by serialize
+    pub fn deserialize(bytes: &[u8]) -> Result<VMProgram, String> {
+        let mut reader = ByteReader { bytes, pos: 0 };
+        if reader.take(BYTECODE_MAGIC.len())? != BYTECODE_MAGIC {
+            return Err("not a bytecode file".to_string());
+        }
+
+        let mut functions = Vec::new();
+        for _ in 0..reader.u32()? {
+            let name = reader.string()?;
+            let entry = reader.u64()? as usize;
+            let params = (0..reader.u32()?).map(|_| reader.string()).collect::<Result<_, _>>()?;
+            functions.push(FunctionInfo { name, entry, params });
+        }
+
+        let mut instrs = Vec::new();
+        while reader.pos < bytes.len() {
+            let opcode = reader.take(1)?[0];
+            let instr = match opcode {
+                0 => VMInstr::PushInt(reader.u64()? as i64),
+                1 => VMInstr::PushBool(reader.take(1)?[0] != 0),
+                2 => VMInstr::PushStr(reader.string()?),
+                3 => VMInstr::Load(reader.string()?),
+                4 => VMInstr::Store(reader.string()?),
+                5 => VMInstr::Add,
+                6 => VMInstr::Sub,
+                7 => VMInstr::Mul,
+                8 => VMInstr::Div,
+                9 => VMInstr::Concat,
+                10 => VMInstr::Eq,
+                11 => VMInstr::Ne,
+                12 => VMInstr::Ret,
+                13 => VMInstr::Jump(reader.u64()? as usize),
+                14 => VMInstr::JumpIfFalse(reader.u64()? as usize),
+                15 => VMInstr::Call(reader.u64()? as usize, reader.u64()? as usize),
+                16 => VMInstr::Print,
+                17 => VMInstr::Dup,
+                18 => VMInstr::Neg,
+                19 => VMInstr::Not,
+                20 => VMInstr::Lt,
+                21 => VMInstr::Gt,
+                22 => VMInstr::Le,
+                23 => VMInstr::Ge,
+                24 => VMInstr::Mod,
+                25 => VMInstr::PushChar(reader.char()?),
+                26 => VMInstr::MakeArray(reader.u64()? as usize),
+                27 => VMInstr::Index,
+                28 => VMInstr::PushFloat(f64::from_bits(reader.u64()?)),
+                29 => VMInstr::FAdd,
+                30 => VMInstr::FSub,
+                31 => VMInstr::FMul,
+                32 => VMInstr::FDiv,
+                33 => VMInstr::IntToFloat,
+                34 => VMInstr::Pop,
+                35 => VMInstr::Repeat,
+                _ => return Err(format!("unknown opcode {} at byte {}", opcode, reader.pos - 1)),
+            };
+            instrs.push(instr);
+        }
+        Ok(VMProgram { instrs, functions })
+    }
+}
+
+// walks the bytes of a bytecode file, running off the end is an error
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let end = self.pos + n;
+        let slice = self.bytes.get(self.pos..end).ok_or("bytecode ends in the middle of an instruction")?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("took 4 bytes")))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        let bytes = self.take(8)?;
+        Ok(u64::from_le_bytes(bytes.try_into().expect("took 8 bytes")))
+    }
+
+    fn char(&mut self) -> Result<char, String> {
+        let code = self.u32()?;
+        char::from_u32(code).ok_or_else(|| format!("{} is not a valid char in bytecode", code))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u32()? as usize;
+        let text = self.take(len)?;
+        String::from_utf8(text.to_vec()).map_err(|_| "string in bytecode is not valid utf8".to_string())
+    }
+}
+
+// ===== disassembler =====
+// one instruction per line prefixed by its index. Every index something jumps
+// or calls to gets a label printed on its own line in front of it, and jumps
+// and calls show that label: 0004  JumpIfFalse -> L1. Function entries are
+// labelled with the function's name, jump targets are numbered in program order
+pub fn disassemble(prog: &VMProgram) -> String {
+    let mut targets: Vec<usize> = prog
+        .instrs
+        .iter()
+        .filter_map(jump_target)
+        .filter(|&target| prog.function_at(target).is_none())
+        .collect();
+    targets.sort_unstable();
+    targets.dedup();
+    let mut labels: HashMap<usize, String> =
+        targets.into_iter().enumerate().map(|(n, index)| (index, format!("L{}", n))).collect();
+    labels.extend(prog.functions.iter().map(|func| (func.entry, func.name.clone())));
+
+    let mut out = String::new();
+    for (i, instr) in prog.instrs.iter().enumerate() {
+        if let Some(label) = labels.get(&i) {
+            out.push_str(&format!("{}:\n", label));
+        }
+        out.push_str(&format!("{:04}  {}\n", i, format_instr(instr, &labels)));
+    }
+    out
+}
+
+fn jump_target(instr: &VMInstr) -> Option<usize> {
+    match instr {
+        VMInstr::Jump(target) | VMInstr::JumpIfFalse(target) | VMInstr::Call(target, _) => Some(*target),
+        _ => None,
+    }
+}
+
+fn format_instr(instr: &VMInstr, labels: &HashMap<usize, String>) -> String {
+    // a target past the end has no label, show the raw index so it still stands out
+    let label = |target: &usize| labels.get(target).cloned().unwrap_or_else(|| format!("{:04}", target));
+    match instr {
+        VMInstr::PushInt(n) => format!("PushInt {}", n),
+        VMInstr::PushFloat(n) => format!("PushFloat {:?}", n),
+        VMInstr::PushBool(b) => format!("PushBool {}", b),
+        VMInstr::PushStr(s) => format!("PushStr {:?}", s),
+        VMInstr::PushChar(c) => format!("PushChar {:?}", c),
+        VMInstr::Load(name) => format!("Load {}", name),
+        VMInstr::Store(name) => format!("Store {}", name),
+        VMInstr::Jump(target) => format!("Jump -> {}", label(target)),
+        VMInstr::JumpIfFalse(target) => format!("JumpIfFalse -> {}", label(target)),
+        VMInstr::Call(target, argc) => format!("Call -> {} ({} args)", label(target), argc),
+        VMInstr::MakeArray(len) => format!("MakeArray {}", len),
+        other => format!("{:?}", other),
+    }
 }
 
 // ===== the VM itself =====
 pub struct VM {
     stack: Vec<VMValue>,     // evaluation stack
-    frames: Vec<Frame>,      // call stack (frame 0 is global)
+    frames: Vec<Frame>,      // call stack (frame 0 belongs to the entry stub)
     pub ip: usize,             // instruction pointer (index in instrs)
-
+    max_depth: usize,        // most frames allowed before a call fails with StackOverflow
+    output: Vec<String>,     // lines written by Print
+    trace: Option<Vec<String>>, // one line per executed instruction when tracing is on
+    counts: [u64; OPCODE_NAMES.len()], // how many times each opcode ran, by VMInstr::opcode
+    breakpoints: HashSet<usize>, // instruction indices run and resume stop in front of
+    paused: bool,            // the last run or resume stopped at a breakpoint
+    max_steps: usize,        // most instructions a run may execute before failing with StepLimitExceeded
+    steps: usize,            // instructions executed since run started
 }
 
+// default call depth limit, deep enough for any sane recursion
+pub(crate) const DEFAULT_MAX_DEPTH: usize = 10_000;
+
+// default instruction budget for one run, far more than any terminating test program needs
+pub(crate) const DEFAULT_MAX_STEPS: usize = 100_000_000;
+
+// how many values from the top of the stack each trace line shows
+const TRACE_STACK_DEPTH: usize = 3;
+
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl VM {
     /// Create a new VM with an empty global frame
     pub fn new() -> Self {
         Self {
             stack: Vec::new(),
-            frames: vec![Frame::new()],
+            frames: vec![Frame::new(0)],
             ip: 0, // start at first instruction
+            max_depth: DEFAULT_MAX_DEPTH,
+            output: Vec::new(),
+            trace: None,
+            counts: [0; OPCODE_NAMES.len()],
+            breakpoints: HashSet::new(),
+            paused: false,
+            max_steps: DEFAULT_MAX_STEPS,
+            steps: 0,
         }
     }
 
+    /// Everything the program printed so far, one entry per Print
+    pub fn output(&self) -> &[String] {
+        &self.output
+    }
+
+    /// Turn on recording of every executed instruction along with the top of the stack
+    pub fn enable_trace(&mut self) {
+        self.trace = Some(Vec::new());
+    }
+
+    /// The recorded trace, empty unless enable_trace was called
+    pub fn trace(&self) -> &[String] {
+        self.trace.as_deref().unwrap_or(&[])
+    }
+
+    /// How many times each opcode executed during the last run, opcodes that never ran are left out
+    pub fn instruction_counts(&self) -> HashMap<&'static str, u64> {
+        OPCODE_NAMES
+            .iter()
+            .zip(self.counts)
+            .filter(|(_, count)| *count > 0)
+            .map(|(name, count)| (*name, count))
+            .collect()
+    }
+
+    /// Make run and resume stop before executing the instruction at this index
+    pub fn set_breakpoint(&mut self, ip: usize) {
+        self.breakpoints.insert(ip);
+    }
+
+    pub fn clear_breakpoint(&mut self, ip: usize) {
+        self.breakpoints.remove(&ip);
+    }
+
+    /// Whether the last run or resume stopped at a breakpoint instead of finishing
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// The evaluation stack, top of the stack last
+    pub fn stack(&self) -> &[VMValue] {
+        &self.stack
+    }
+
+    /// Variables of the function that is currently running
+    pub fn locals(&self) -> &HashMap<String, VMValue> {
+        &self.frames.last().expect("No call frame").locals
+    }
+
+    /// Change how deep calls can nest before the VM gives up with StackOverflow
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
+    }
+
+    /// Change how many instructions a run may execute before the VM gives up with StepLimitExceeded
+    pub fn set_max_steps(&mut self, steps: usize) {
+        self.max_steps = steps;
+    }
+
     /// Helper: push a value onto the evaluation stack
     fn push(&mut self, v: VMValue) {
         self.stack.push(v);
     }
 
     /// Helper: pop a value from the evaluation stack
-    fn pop(&mut self) -> VMValue {
-        self.stack.pop().expect("VM stack underflow")
+    fn pop(&mut self) -> Result<VMValue, VMError> {
+        self.stack.pop().ok_or(VMError::StackUnderflow)
+    }
+
+    /// Helper: pop the two integer operands of an arithmetic instruction
+    fn pop_ints(&mut self, op: &str) -> Result<(i64, i64), VMError> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        match (a, b) {
+            (VMValue::Int(a), VMValue::Int(b)) => Ok((a, b)),
+            (a, b) => Err(VMError::TypeMismatch(format!(
+                "{} expects two integers, got {:?} and {:?}",
+                op, a, b
+            ))),
+        }
+    }
+
+    // pops the right operand then the left one of a float op
+    fn pop_floats(&mut self, op: &str) -> Result<(f64, f64), VMError> {
+        let b = self.pop()?;
+        let a = self.pop()?;
+        match (a, b) {
+            (VMValue::Float(a), VMValue::Float(b)) => Ok((a, b)),
+            (a, b) => Err(VMError::TypeMismatch(format!(
+                "{} expects two floats, got {:?} and {:?}",
+                op, a, b
+            ))),
+        }
     }
 
     /// Helper: store a variable in the current frame
@@ -90,132 +527,1041 @@ impl VM {
     }
 
     /// Helper: load a variable from the current frame
+    // the language has no globals, so only the running function's locals are
+    // searched and a function can't see the locals of whoever called it
     fn get_var(&self, name: &str) -> Option<VMValue> {
         let frame = self.frames.last().expect("No call frame");
         frame.locals.get(name).cloned()
     }
 
-    /// Execute a VMProgram and return an optional VMValue from the first Ret.
-    /// This is a simple interpreter loop. It returns the top-of-stack value
-    /// when it sees a `Ret` instruction.
-    pub fn run(&mut self, prog: &VMProgram) -> Option<VMValue> {
+    /// Execute a VMProgram from the start. This is a simple interpreter loop.
+    /// It returns Halted with the top-of-stack value when the outermost `Ret`
+    /// runs, or a VMError if the program is malformed.
+    /// Integer arithmetic wraps on overflow.
+    /// If it reaches a breakpoint it stops before that instruction and returns
+    /// Paused, resume carries on from there.
+    /// Everything left from an earlier run (stack, frames, output) is cleared first.
+    pub fn run(&mut self, prog: &VMProgram) -> Result<StepResult, VMError> {
+        self.stack.clear();
+        self.frames = vec![Frame::new(0)];
+        self.output.clear();
+        if let Some(trace) = &mut self.trace {
+            trace.clear();
+        }
         self.ip = 0;
-        while self.ip < prog.instrs.len() {
-            let instr = &prog.instrs[self.ip];
-            self.ip += 1; // move to next instruction by default
+        self.counts = [0; OPCODE_NAMES.len()];
+        self.steps = 0;
+        self.run_to_break(prog, false)
+    }
 
-            match instr {
-                VMInstr::PushInt(n) => self.stack.push(VMValue::Int(*n)),
-                VMInstr::PushBool(b) => self.stack.push(VMValue::Bool(*b)),
-                VMInstr::PushStr(s) => self.stack.push(VMValue::Str(s.clone())),
-
-                VMInstr::Add => {
-                    let b = self.stack.pop().expect("Stack underflow");
-                    let a = self.stack.pop().expect("Stack underflow");
-                    if let (VMValue::Int(a), VMValue::Int(b)) = (a, b) {
-                        self.stack.push(VMValue::Int(a + b));
-                    } else {
-                        panic!("Add expects two integers");
-                    }
+    /// Carry on a run that stopped at a breakpoint
+    pub fn resume(&mut self, prog: &VMProgram) -> Result<StepResult, VMError> {
+        self.run_to_break(prog, true)
+    }
+
+    // steps until the program halts or ip lands on a breakpoint, the first
+    // instruction skips the check so resuming doesn't stop where it paused
+    fn run_to_break(&mut self, prog: &VMProgram, mut skip_break: bool) -> Result<StepResult, VMError> {
+        self.paused = false;
+        loop {
+            if !skip_break && self.breakpoints.contains(&self.ip) {
+                self.paused = true;
+                return Ok(StepResult::Paused);
+            }
+            skip_break = false;
+            if let StepResult::Halted(value) = self.step(prog)? {
+                return Ok(StepResult::Halted(value));
+            }
+        }
+    }
+
+    /// Run to the end, carrying on past any breakpoints, and give back what main returned
+    pub fn run_to_end(&mut self, prog: &VMProgram) -> Result<Option<VMValue>, VMError> {
+        let mut result = self.run(prog)?;
+        while result == StepResult::Paused {
+            result = self.resume(prog)?;
+        }
+        match result {
+            StepResult::Halted(value) => Ok(value),
+            StepResult::Running | StepResult::Paused => unreachable!("run only stops when halted or paused"),
+        }
+    }
+
+    /// Execute exactly the instruction at ip
+    pub fn step(&mut self, prog: &VMProgram) -> Result<StepResult, VMError> {
+        let Some(instr) = prog.instrs.get(self.ip) else {
+            return Ok(StepResult::Halted(None));
+        };
+        if self.steps >= self.max_steps {
+            return Err(VMError::StepLimitExceeded(self.max_steps));
+        }
+        self.steps += 1;
+        self.counts[instr.opcode()] += 1;
+        if let Some(trace) = &mut self.trace {
+            //top of the stack first
+            let top: Vec<&VMValue> = self.stack.iter().rev().take(TRACE_STACK_DEPTH).collect();
+            trace.push(format!("{:04}  {:<28} stack: {:?}", self.ip, format_instr(instr, &HashMap::new()), top));
+        }
+        self.ip += 1; // move to next instruction by default
+
+        match instr {
+            VMInstr::PushInt(n) => self.push(VMValue::Int(*n)),
+            VMInstr::PushBool(b) => self.push(VMValue::Bool(*b)),
+            VMInstr::PushStr(s) => self.push(VMValue::Str(s.clone())),
+            VMInstr::PushChar(c) => self.push(VMValue::Char(*c)),
+            VMInstr::PushFloat(n) => self.push(VMValue::Float(*n)),
+
+            VMInstr::Add => {
+                let (a, b) = self.pop_ints("Add")?;
+                self.push(VMValue::Int(a.wrapping_add(b)));
+            }
+            VMInstr::Sub => {
+                let (a, b) = self.pop_ints("Sub")?;
+                self.push(VMValue::Int(a.wrapping_sub(b)));
+            }
+            VMInstr::Mul => {
+                let (a, b) = self.pop_ints("Mul")?;
+                self.push(VMValue::Int(a.wrapping_mul(b)));
+            }
+            VMInstr::Div => {
+                let (a, b) = self.pop_ints("Div")?;
+                if b == 0 {
+                    return Err(VMError::DivisionByZero);
+                }
+                self.push(VMValue::Int(a.wrapping_div(b)));
+            }
+            VMInstr::Mod => {
+                let (a, b) = self.pop_ints("Mod")?;
+                if b == 0 {
+                    return Err(VMError::DivisionByZero);
                 }
+                self.push(VMValue::Int(a.wrapping_rem(b)));
+            }
 
-                VMInstr::Store(name) => {
-                    let val = self.stack.pop().expect("Stack underflow on Store");
-                    self.set_var(name, val);
+            VMInstr::FAdd => {
+                let (a, b) = self.pop_floats("FAdd")?;
+                self.push(VMValue::Float(a + b));
+            }
+            VMInstr::FSub => {
+                let (a, b) = self.pop_floats("FSub")?;
+                self.push(VMValue::Float(a - b));
+            }
+            VMInstr::FMul => {
+                let (a, b) = self.pop_floats("FMul")?;
+                self.push(VMValue::Float(a * b));
+            }
+            VMInstr::FDiv => {
+                let (a, b) = self.pop_floats("FDiv")?;
+                self.push(VMValue::Float(a / b));
+            }
+            VMInstr::IntToFloat => match self.pop()? {
+                VMValue::Int(n) => self.push(VMValue::Float(n as f64)),
+                other => {
+                    return Err(VMError::TypeMismatch(format!("IntToFloat expects an integer, got {:?}", other)))
                 }
+            },
 
-                VMInstr::Load(name) => {
-                    if let Some(val) = self.get_var(name) {
-                        self.stack.push(val);
-                    } else {
-                        panic!("Undefined variable: {}", name);
+            VMInstr::Concat => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                if let (VMValue::Str(a), VMValue::Str(b)) = (&a, &b) {
+                    self.push(VMValue::Str(format!("{}{}", a, b)));
+                } else {
+                    return Err(VMError::TypeMismatch(format!(
+                        "Concat expects two strings, got {:?} and {:?}",
+                        a, b
+                    )));
+                }
+            }
+
+            VMInstr::Repeat => {
+                let count = self.pop()?;
+                let s = self.pop()?;
+                let (VMValue::Str(s), VMValue::Int(count)) = (&s, &count) else {
+                    return Err(VMError::TypeMismatch(format!(
+                        "Repeat expects a string and an integer, got {:?} and {:?}",
+                        s, count
+                    )));
+                };
+                let repeated = repeat(s, *count).ok_or(VMError::BadRepeatCount(*count))?;
+                self.push(VMValue::Str(repeated));
+            }
+
+            VMInstr::Eq | VMInstr::Ne => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let equal = a == b;
+                let result = if matches!(instr, VMInstr::Eq) { equal } else { !equal };
+                self.push(VMValue::Bool(result));
+            }
+
+            VMInstr::Lt | VMInstr::Gt | VMInstr::Le | VMInstr::Ge => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let ordering = match (&a, &b) {
+                    (VMValue::Int(a), VMValue::Int(b)) => Some(a.cmp(b)),
+                    (VMValue::Str(a), VMValue::Str(b)) => Some(a.cmp(b)),
+                    //NaN isn't ordered against anything, every comparison with it is false
+                    (VMValue::Float(a), VMValue::Float(b)) => a.partial_cmp(b),
+                    _ => {
+                        return Err(VMError::TypeMismatch(format!(
+                            "Comparison expects two numbers of the same type or two strings, got {:?} and {:?}",
+                            a, b
+                        )))
                     }
+                };
+                let result = ordering.is_some_and(|ordering| match instr {
+                    VMInstr::Lt => ordering.is_lt(),
+                    VMInstr::Gt => ordering.is_gt(),
+                    VMInstr::Le => ordering.is_le(),
+                    _ => ordering.is_ge(),
+                });
+                self.push(VMValue::Bool(result));
+            }
+
+            VMInstr::Store(name) => {
+                let val = self.pop()?;
+                self.set_var(name, val);
+            }
+
+            VMInstr::Load(name) => {
+                let val = self
+                    .get_var(name)
+                    .ok_or_else(|| VMError::UndefinedVariable(name.clone()))?;
+                self.push(val);
+            }
+
+            VMInstr::Call(target, argc) => {
+                if self.stack.len() < *argc {
+                    return Err(VMError::StackUnderflow);
+                }
+                if self.frames.len() >= self.max_depth {
+                    return Err(VMError::StackOverflow);
                 }
+                // the arguments were pushed in order, they become the callee's params
+                let params = prog.function_at(*target).map_or(&[][..], |func| &func.params[..]);
+                if params.len() != *argc {
+                    return Err(VMError::ArgumentCount { expected: params.len(), got: *argc });
+                }
+                let mut frame = Frame::new(self.ip);
+                let args = self.stack.split_off(self.stack.len() - argc);
+                frame.locals.extend(params.iter().cloned().zip(args));
+                self.frames.push(frame);
+                self.ip = *target;
+            }
+
+            VMInstr::Neg => match self.pop()? {
+                VMValue::Int(n) => self.push(VMValue::Int(n.wrapping_neg())),
+                VMValue::Float(n) => self.push(VMValue::Float(-n)),
+                other => {
+                    return Err(VMError::TypeMismatch(format!("Neg expects a number, got {:?}", other)))
+                }
+            },
+            VMInstr::Not => match self.pop()? {
+                VMValue::Bool(b) => self.push(VMValue::Bool(!b)),
+                other => {
+                    return Err(VMError::TypeMismatch(format!("Not expects a boolean, got {:?}", other)))
+                }
+            },
 
-                VMInstr::Ret => {
-                    return self.stack.pop();
+            VMInstr::MakeArray(len) => {
+                if self.stack.len() < *len {
+                    return Err(VMError::StackUnderflow);
                 }
+                let elements = self.stack.split_off(self.stack.len() - len);
+                self.push(VMValue::Array(elements));
+            }
 
-                // optional: add these when you do control flow
-                VMInstr::Jump(target) => {
-                    self.ip = *target;
-                    continue;
+            VMInstr::Index => {
+                let index = self.pop()?;
+                let array = self.pop()?;
+                match (array, index) {
+                    (VMValue::Array(elements), VMValue::Int(i)) => {
+                        let len = elements.len();
+                        let elem = usize::try_from(i)
+                            .ok()
+                            .and_then(|i| elements.into_iter().nth(i))
+                            .ok_or(VMError::IndexOutOfBounds { index: i, len })?;
+                        self.push(elem);
+                    }
+                    (a, i) => {
+                        return Err(VMError::TypeMismatch(format!(
+                            "Index expects an array and an integer, got {:?} and {:?}",
+                            a, i
+                        )))
+                    }
+                }
+            }
+
+            VMInstr::Pop => {
+                self.pop()?;
+            }
+
+            VMInstr::Dup => {
+                let top = self.stack.last().cloned().ok_or(VMError::StackUnderflow)?;
+                self.push(top);
+            }
+
+            VMInstr::Print => {
+                let value = self.pop()?;
+                self.output.push(value.to_string());
+            }
+
+            // returning from a call goes back to the caller with the value
+            // left on the stack; returning from the outermost frame ends the run
+            VMInstr::Ret => {
+                let value = self.stack.pop();
+                if self.frames.len() == 1 {
+                    return Ok(StepResult::Halted(value));
                 }
-                VMInstr::JumpIfFalse(target) => {
-                    if let Some(VMValue::Bool(cond)) = self.stack.pop() {
+                let frame = self.frames.pop().expect("No call frame");
+                self.ip = frame.return_ip;
+                if let Some(value) = value {
+                    self.push(value);
+                }
+            }
+
+            // optional: add these when you do control flow
+            VMInstr::Jump(target) => {
+                self.ip = *target;
+            }
+            VMInstr::JumpIfFalse(target) => {
+                match self.pop()? {
+                    VMValue::Bool(cond) => {
                         if !cond {
                             self.ip = *target;
-                            continue;
                         }
-                    } else {
-                        panic!("Expected bool on JumpIfFalse");
+                    }
+                    other => {
+                        return Err(VMError::TypeMismatch(format!(
+                            "JumpIfFalse expects a bool, got {:?}",
+                            other
+                        )))
                     }
                 }
-
-                _ => {}
             }
         }
 
-        None
+        Ok(StepResult::Running)
     }
 
 }
 
+// what happened after a single step
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepResult {
+    Running,
+    Paused,                  // run or resume stopped in front of a breakpoint, never from step
+    Halted(Option<VMValue>), // the program finished, with what main returned
+}
+
+// ===== errors the VM can hit while running a program =====
+#[derive(Debug, Clone, PartialEq)]
+pub enum VMError {
+    StackUnderflow,
+    StackOverflow,             // calls nested deeper than the VM's max depth
+    TypeMismatch(String),      // operand had the wrong type for the instruction
+    UndefinedVariable(String), // Load of a name that was never stored
+    DivisionByZero,
+    IndexOutOfBounds { index: i64, len: usize },
+    ArgumentCount { expected: usize, got: usize }, // a Call passed a different number of args than the callee takes
+    StepLimitExceeded(usize),  // ran more instructions than the VM's max steps, likely an endless loop
+    BadRepeatCount(i64),       // a string repeated a negative number of times, or too many to fit in memory
+}
+
+impl fmt::Display for VMError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VMError::StackUnderflow => write!(f, "stack underflow"),
+            VMError::StackOverflow => write!(f, "stack overflow: too many nested calls"),
+            VMError::TypeMismatch(msg) => write!(f, "type mismatch: {}", msg),
+            VMError::UndefinedVariable(name) => write!(f, "undefined variable '{}'", name),
+            VMError::DivisionByZero => write!(f, "division by zero"),
+            VMError::IndexOutOfBounds { index, len } => {
+                write!(f, "index {} is out of bounds for an array of length {}", index, len)
+            }
+            VMError::ArgumentCount { expected, got } => {
+                write!(f, "call passed {} arguments to a function that takes {}", got, expected)
+            }
+            VMError::StepLimitExceeded(limit) => {
+                write!(f, "step limit of {} instructions exceeded, the program may be stuck in a loop", limit)
+            }
+            VMError::BadRepeatCount(count) => write!(f, "can't repeat a string {} times", count),
+        }
+    }
+}
+
+impl std::error::Error for VMError {}
+
 // ===== Lowering from IR to VMProgram (simple deterministic lowering) =====
-pub fn lower_ir_to_vm(ir: &[IRInstr]) -> VMProgram {
+// Lowers a single function body on its own. It can't contain calls since
+// there are no other functions to jump to; use lower_program for that.
+pub fn lower_ir_to_vm(ir: &[IRInstr]) -> Result<VMProgram, String> {
     let mut instrs: Vec<VMInstr> = Vec::new();
+    let mut calls: Vec<(usize, String)> = Vec::new();
+    lower_function(&mut instrs, &[], ir, &HashMap::new(), &mut calls)?;
+    if let Some((_, name)) = calls.first() {
+        return Err(format!("Call to unknown function '{}'", name));
+    }
+    Ok(VMProgram { instrs, functions: Vec::new() })
+}
+
+// Lowers every function into one program. It starts with a small stub that
+// calls the entry function and returns its result, followed by each body.
+// Call targets are patched once every function's start index is known.
+pub fn lower_program(funcs: &[IRFunction], entry: &str) -> Result<VMProgram, String> {
+    let mut instrs: Vec<VMInstr> = vec![VMInstr::Call(0, 0), VMInstr::Ret];
+    let mut calls: Vec<(usize, String)> = vec![(0, entry.to_string())]; // (call index, callee)
+    let mut functions: Vec<FunctionInfo> = Vec::new();
+    let returns: HashMap<String, Type> = funcs
+        .iter()
+        .map(|func| (func.name.clone(), func.return_type.clone()))
+        .collect();
+
+    for func in funcs {
+        let params = func.params.iter().map(|(name, _)| name.clone()).collect();
+        functions.push(FunctionInfo { name: func.name.clone(), entry: instrs.len(), params });
+        lower_function(&mut instrs, &func.params, &func.code, &returns, &mut calls)?;
+    }
+
+    for (index, name) in calls {
+        let target = functions
+            .iter()
+            .find(|func| func.name == name)
+            .ok_or_else(|| format!("Call to unknown function '{}'", name))?
+            .entry;
+        if let VMInstr::Call(t, _) = &mut instrs[index] {
+            *t = target;
+        }
+    }
+
+    Ok(VMProgram { instrs, functions })
+}
+
+// ===== peephole optimizer =====
+// Rewrites short instruction windows into cheaper ones:
+//  - Store t; Load t  -> (nothing) when t is a temp no other Load in the function reads
+//  - Load x; Store x  -> (nothing)
+//  - Store t          -> Pop when t is a temp no Load in the function reads
+// A window is left alone when a jump lands inside it, since the code arriving
+// there expects the full sequence. Jump and call targets are remapped at the end.
+pub fn peephole(prog: VMProgram) -> VMProgram {
+    let VMProgram { instrs: old, mut functions } = prog;
+
+    // a function's body runs from its call target up to the next one
+    let mut starts: Vec<usize> = old
+        .iter()
+        .filter_map(|instr| match instr {
+            VMInstr::Call(target, _) => Some(*target),
+            _ => None,
+        })
+        .collect();
+    starts.push(0);
+    starts.sort_unstable();
+    starts.dedup();
+    let function_of = |i: usize| starts.partition_point(|&start| start <= i);
+
+    // how many Loads of each name are in each function
+    let mut loads: HashMap<(usize, &str), usize> = HashMap::new();
+    for (i, instr) in old.iter().enumerate() {
+        if let VMInstr::Load(name) = instr {
+            *loads.entry((function_of(i), name.as_str())).or_default() += 1;
+        }
+    }
+
+    let jumped_to: HashSet<usize> = old.iter().filter_map(jump_target).collect();
+
+    let mut instrs = Vec::with_capacity(old.len());
+    // new_index[i] is where old instruction i (or whatever replaced it) ended up
+    let mut new_index = Vec::with_capacity(old.len() + 1);
+    let mut i = 0;
+    while i < old.len() {
+        let second_free = !jumped_to.contains(&(i + 1));
+        match (&old[i], old.get(i + 1)) {
+            // temps are never read outside their function, so the one Load is the only reader
+            (VMInstr::Store(a), Some(VMInstr::Load(b)))
+                if a == b
+                    && second_free
+                    && is_temp_name(a)
+                    && loads.get(&(function_of(i), a.as_str())) == Some(&1) =>
+            {
+                new_index.extend([instrs.len(), instrs.len()]);
+                i += 2;
+            }
+            (VMInstr::Load(a), Some(VMInstr::Store(b))) if a == b && second_free => {
+                new_index.extend([instrs.len(), instrs.len()]);
+                i += 2;
+            }
+            (VMInstr::Store(a), _) if is_temp_name(a) && !loads.contains_key(&(function_of(i), a.as_str())) => {
+                new_index.push(instrs.len());
+                instrs.push(VMInstr::Pop);
+                i += 1;
+            }
+            (instr, _) => {
+                new_index.push(instrs.len());
+                instrs.push(instr.clone());
+                i += 1;
+            }
+        }
+    }
+    new_index.push(instrs.len());
+
+    for instr in &mut instrs {
+        match instr {
+            VMInstr::Jump(target) | VMInstr::JumpIfFalse(target) | VMInstr::Call(target, _) => {
+                *target = new_index[*target];
+            }
+            _ => {}
+        }
+    }
+    for func in &mut functions {
+        func.entry = new_index[func.entry];
+    }
+
+    VMProgram { instrs, functions }
+}
+
+// Appends one function body to instrs. Calls are recorded in `calls` with a
+// placeholder target for the caller to patch, `returns` gives the return type
+// of each callee.
+//
+// IR operands are just names, so lowering tracks the type each name was last
+// assigned to pick the right opcode (e.g. Concat instead of Add for strings).
+// Operators the VM has no opcode for are reported as an error.
+//
+// Jumps are lowered in two passes: the first records the VM index each label
+// lands on and emits jumps with a placeholder target, the second patches every
+// jump with the index of its label. Labels are local to the function.
+//
+// A temp written and read exactly once isn't stored. A computed one is left
+// on the stack and the instruction reading it skips its Load, a literal is
+// only pushed where it's read. When the temps left on the stack don't line up
+// with what the next instruction loads, or control flow is about to leave the
+// block, they're stored after all (see load_operands).
+fn lower_function(
+    instrs: &mut Vec<VMInstr>,
+    params: &[(String, Type)],
+    ir: &[IRInstr],
+    returns: &HashMap<String, Type>,
+    calls: &mut Vec<(usize, String)>,
+) -> Result<(), String> {
+    let mut types: HashMap<String, Type> = HashMap::new();
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut fixups: Vec<(usize, String)> = Vec::new(); // (jump index, label name)
+    let mut reads: HashMap<&String, usize> = HashMap::new();
+    let mut writes: HashMap<&String, usize> = HashMap::new();
+    for instr in ir {
+        let (def, uses) = instr.defs_and_uses();
+        for name in uses {
+            *reads.entry(name).or_default() += 1;
+        }
+        if let Some(def) = def {
+            *writes.entry(def).or_default() += 1;
+        }
+    }
+    let single_use = |name: &String| is_temp_name(name) && reads.get(name) == Some(&1) && writes.get(name) == Some(&1);
+    let mut kept = Kept::default();
+
+    // the VM's Call stores the arguments under the param names before the body runs
+    types.extend(params.iter().cloned());
 
     for instr in ir {
         match instr {
-            IRInstr::Assign(target, value) => match value {
-                IRValue::Int(n) => {
-                    instrs.push(VMInstr::PushInt(*n));
-                    instrs.push(VMInstr::Store(target.clone()));
-                }
-                IRValue::Bool(b) => {
-                    instrs.push(VMInstr::PushBool(*b));
-                    instrs.push(VMInstr::Store(target.clone()));
-                }
-                IRValue::Str(s) => {
-                    instrs.push(VMInstr::PushStr(s.clone()));
-                    instrs.push(VMInstr::Store(target.clone()));
-                }
-                IRValue::Var(v) | IRValue::Temp(v) => {
-                    // copy from another variable/temp
-                    instrs.push(VMInstr::Load(v.clone()));
-                    instrs.push(VMInstr::Store(target.clone()));
+            IRInstr::Assign(target, value) => {
+                let ty = match value {
+                    IRValue::Int(_) => Type::Int,
+                    IRValue::Float(_) => Type::Float,
+                    IRValue::Bool(_) => Type::Bool,
+                    IRValue::Str(_) => Type::Str,
+                    IRValue::Char(_) => Type::Char,
+                    IRValue::Var(v) | IRValue::Temp(v) => {
+                        types.get(v).cloned().unwrap_or(Type::Unknown)
+                    }
+                };
+                types.insert(target.clone(), ty);
+                if single_use(target) && !matches!(value, IRValue::Var(_) | IRValue::Temp(_)) {
+                    kept.literals.push((target.clone(), value.clone()));
+                    continue;
                 }
-            },
+                push_value(instrs, &mut kept, value);
+                store_result(instrs, &mut kept, target, single_use(target));
+            }
 
             IRInstr::BinaryOp(result, left, op, right) => {
+                let is_str = types.get(left) == Some(&Type::Str);
+                // an Int next to a Float in arithmetic is converted as it's loaded
+                let is_float = types.get(left) == Some(&Type::Float) || types.get(right) == Some(&Type::Float);
+                let arithmetic = matches!(op.as_str(), "+" | "-" | "*" | "/");
+
                 // load left then right (order chosen here)
-                instrs.push(VMInstr::Load(left.clone()));
-                instrs.push(VMInstr::Load(right.clone()));
+                let to_float = |operand: &String| is_float && arithmetic && types.get(operand) == Some(&Type::Int);
+                load_operands(instrs, &mut kept, &[left, right], to_float, false);
 
-                match op.as_str() {
-                    "+" => instrs.push(VMInstr::Add),
-                    "-" => instrs.push(VMInstr::Sub),
-                    "*" => instrs.push(VMInstr::Mul),
-                    "/" => instrs.push(VMInstr::Div),
-                    _ => instrs.push(VMInstr::Add), // fallback; ideally handle other ops
-                }
+                let (opcode, ty) = match op.as_str() {
+                    "+" if is_float => (VMInstr::FAdd, Type::Float),
+                    "-" if is_float => (VMInstr::FSub, Type::Float),
+                    "*" if is_float => (VMInstr::FMul, Type::Float),
+                    "/" if is_float => (VMInstr::FDiv, Type::Float),
+                    "+" if is_str => (VMInstr::Concat, Type::Str),
+                    "*" if is_str => (VMInstr::Repeat, Type::Str),
+                    "+" => (VMInstr::Add, Type::Int),
+                    "-" => (VMInstr::Sub, Type::Int),
+                    "*" => (VMInstr::Mul, Type::Int),
+                    "/" => (VMInstr::Div, Type::Int),
+                    "%" => (VMInstr::Mod, Type::Int),
+                    "==" => (VMInstr::Eq, Type::Bool),
+                    "!=" => (VMInstr::Ne, Type::Bool),
+                    "<" => (VMInstr::Lt, Type::Bool),
+                    ">" => (VMInstr::Gt, Type::Bool),
+                    "<=" => (VMInstr::Le, Type::Bool),
+                    ">=" => (VMInstr::Ge, Type::Bool),
+                    _ => return Err(format!("No VM instruction for operator '{}'", op)),
+                };
+                instrs.push(opcode);
+
+                types.insert(result.clone(), ty);
+                store_result(instrs, &mut kept, result, single_use(result));
+            }
+
+            IRInstr::UnaryOp(result, op, operand) => {
+                load_operands(instrs, &mut kept, &[operand], |_| false, false);
+                let (opcode, ty) = match op.as_str() {
+                    "-" if types.get(operand) == Some(&Type::Float) => (VMInstr::Neg, Type::Float),
+                    "-" => (VMInstr::Neg, Type::Int),
+                    "!" => (VMInstr::Not, Type::Bool),
+                    _ => return Err(format!("No VM instruction for operator '{}'", op)),
+                };
+                instrs.push(opcode);
+
+                types.insert(result.clone(), ty);
+                store_result(instrs, &mut kept, result, single_use(result));
+            }
+
+            IRInstr::MakeArray(result, elements) => {
+                let elements: Vec<&String> = elements.iter().collect();
+                load_operands(instrs, &mut kept, &elements, |_| false, false);
+                instrs.push(VMInstr::MakeArray(elements.len()));
+                let elem_ty = elements.first().and_then(|e| types.get(*e)).cloned().unwrap_or(Type::Unknown);
+                types.insert(result.clone(), Type::Array(Box::new(elem_ty)));
+                store_result(instrs, &mut kept, result, single_use(result));
+            }
 
-                instrs.push(VMInstr::Store(result.clone()));
+            IRInstr::Index(result, base, index) => {
+                load_operands(instrs, &mut kept, &[base, index], |_| false, false);
+                instrs.push(VMInstr::Index);
+                let ty = match types.get(base) {
+                    Some(Type::Array(elem_ty)) => (**elem_ty).clone(),
+                    _ => Type::Unknown,
+                };
+                types.insert(result.clone(), ty);
+                store_result(instrs, &mut kept, result, single_use(result));
             }
 
+            // the stack is empty between statements, so Ret with nothing
+            // loaded returns no value
             IRInstr::Return(name) => {
-                instrs.push(VMInstr::Load(name.clone()));
+                let name: Vec<&String> = name.iter().collect();
+                load_operands(instrs, &mut kept, &name, |_| false, true);
                 instrs.push(VMInstr::Ret);
             }
+
+            // built-in print doesn't produce a value to store
+            IRInstr::Call(_, name, args) if name == "print" => {
+                let args: Vec<&String> = args.iter().collect();
+                load_operands(instrs, &mut kept, &args, |_| false, false);
+                instrs.push(VMInstr::Print);
+            }
+
+            // whatever is kept below the arguments is safe, the callee only
+            // takes its arguments off the stack
+            IRInstr::Call(result, name, args) => {
+                let args: Vec<&String> = args.iter().collect();
+                load_operands(instrs, &mut kept, &args, |_| false, false);
+                calls.push((instrs.len(), name.clone()));
+                instrs.push(VMInstr::Call(0, args.len()));
+                let ty = returns.get(name).cloned().unwrap_or(Type::Unknown);
+                // a function returning nothing leaves nothing on the stack to store,
+                // and a value nothing reads, like a call used as a statement, is dropped
+                if ty != Type::Unit {
+                    if reads.contains_key(result) {
+                        store_result(instrs, &mut kept, result, single_use(result));
+                    } else {
+                        instrs.push(VMInstr::Pop);
+                    }
+                }
+                types.insert(result.clone(), ty);
+            }
+
+            // labels emit nothing, they just name the next instruction. Code
+            // jumping here doesn't have the kept temps, so they're stored first
+            IRInstr::Label(name) => {
+                flush_kept(instrs, &mut kept);
+                labels.insert(name.clone(), instrs.len());
+            }
+
+            IRInstr::Jump(label) => {
+                flush_kept(instrs, &mut kept);
+                fixups.push((instrs.len(), label.clone()));
+                instrs.push(VMInstr::Jump(0));
+            }
+
+            IRInstr::JumpIfFalse(cond, label) => {
+                load_operands(instrs, &mut kept, &[cond], |_| false, true);
+                fixups.push((instrs.len(), label.clone()));
+                instrs.push(VMInstr::JumpIfFalse(0));
+            }
+
+            IRInstr::Phi(result, _) => return Err(format!("Can't lower phi for '{}', IR has to leave SSA form first", result)),
         }
     }
 
-    VMProgram { instrs }
+    flush_kept(instrs, &mut kept);
+
+    // second pass: point every jump at its label
+    for (index, label) in fixups {
+        let target = *labels
+            .get(&label)
+            .ok_or_else(|| format!("Jump to undefined label '{}'", label))?;
+        match &mut instrs[index] {
+            VMInstr::Jump(t) | VMInstr::JumpIfFalse(t) => *t = target,
+            _ => unreachable!("fixup recorded for a non-jump instruction"),
+        }
+    }
+
+    Ok(())
+}
+
+// what lowering has put off storing
+#[derive(Default)]
+struct Kept {
+    stack: Vec<String>,                 // temps whose value is sitting on the stack, topmost last
+    literals: Vec<(String, IRValue)>,   // temps holding a literal nothing has pushed yet
+}
+
+// pushes the assigned value, a kept temp is already there
+fn push_value(instrs: &mut Vec<VMInstr>, kept: &mut Kept, value: &IRValue) {
+    match value {
+        IRValue::Int(n) => instrs.push(VMInstr::PushInt(*n)),
+        IRValue::Float(n) => instrs.push(VMInstr::PushFloat(*n)),
+        IRValue::Bool(b) => instrs.push(VMInstr::PushBool(*b)),
+        IRValue::Str(s) => instrs.push(VMInstr::PushStr(s.clone())),
+        IRValue::Char(c) => instrs.push(VMInstr::PushChar(*c)),
+        // copy from another variable/temp
+        IRValue::Var(v) | IRValue::Temp(v) => load_operands(instrs, kept, &[v], |_| false, false),
+    }
+}
+
+// Pushes operands in order, converting the ones to_float picks. Kept temps
+// on top of the stack that are exactly the first operands are already in
+// place and aren't loaded again. If a kept temp is read any other way, or
+// all_kept asks for nothing to be left behind and something would be, every
+// kept temp is stored and the operands are loaded as usual.
+fn load_operands(
+    instrs: &mut Vec<VMInstr>,
+    kept: &mut Kept,
+    operands: &[&String],
+    to_float: impl Fn(&String) -> bool,
+    all_kept: bool,
+) {
+    let stack = &kept.stack;
+    let lines_up = |n: usize| {
+        let below = match stack.len().checked_sub(n) {
+            Some(below) => below,
+            None => return false,
+        };
+        stack[below..].iter().zip(operands).all(|(temp, operand)| temp == *operand)
+            // only the topmost one can still be converted once it's there
+            && operands[..n.saturating_sub(1)].iter().all(|operand| !to_float(operand))
+            && operands[n..].iter().all(|operand| !stack[..below].contains(operand))
+            && !(all_kept && below > 0)
+    };
+    let in_place = match (0..=operands.len()).rev().find(|&n| lines_up(n)) {
+        Some(n) => n,
+        None => {
+            flush_kept(instrs, kept);
+            0
+        }
+    };
+    kept.stack.truncate(kept.stack.len() - in_place);
+    if in_place > 0 && to_float(operands[in_place - 1]) {
+        instrs.push(VMInstr::IntToFloat);
+    }
+    for operand in &operands[in_place..] {
+        match kept.literals.iter().position(|(temp, _)| temp == *operand) {
+            Some(i) => {
+                let (_, value) = kept.literals.remove(i);
+                push_value(instrs, kept, &value);
+            }
+            None => instrs.push(VMInstr::Load((*operand).clone())),
+        }
+        if to_float(operand) {
+            instrs.push(VMInstr::IntToFloat);
+        }
+    }
+}
+
+// stores the value on top of the stack into result, or leaves it there
+fn store_result(instrs: &mut Vec<VMInstr>, kept: &mut Kept, result: &str, keep: bool) {
+    if keep {
+        kept.stack.push(result.to_string());
+    } else {
+        instrs.push(VMInstr::Store(result.to_string()));
+    }
+}
+
+// stores every kept temp, topmost first, so the stack is back to empty
+fn flush_kept(instrs: &mut Vec<VMInstr>, kept: &mut Kept) {
+    while let Some(temp) = kept.stack.pop() {
+        instrs.push(VMInstr::Store(temp));
+    }
+    for (temp, value) in std::mem::take(&mut kept.literals) {
+        push_value(instrs, kept, &value);
+        instrs.push(VMInstr::Store(temp));
+    }
 }
 
 // ===== convenience: run IR through lowering and the VM =====
-pub fn run_ir_with_vm(ir: &[IRInstr]) -> Option<VMValue> {
-    let prog = lower_ir_to_vm(ir);
+pub fn run_ir_with_vm(ir: &[IRInstr]) -> Result<Option<VMValue>, String> {
+    let prog = lower_ir_to_vm(ir)?;
     let mut vm = VM::new();
-    vm.run(&prog)
+    vm.run_to_end(&prog).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program(instrs: Vec<VMInstr>) -> VMProgram {
+        VMProgram { instrs, functions: Vec::new() }
+    }
+
+    fn store(name: &str) -> VMInstr {
+        VMInstr::Store(name.to_string())
+    }
+
+    fn load(name: &str) -> VMInstr {
+        VMInstr::Load(name.to_string())
+    }
+
+    #[test]
+    fn peephole_drops_temp_round_trip() {
+        let prog = program(vec![VMInstr::PushInt(1), store("%1"), load("%1"), VMInstr::Ret]);
+        assert_eq!(peephole(prog).instrs, vec![VMInstr::PushInt(1), VMInstr::Ret]);
+    }
+
+    #[test]
+    fn peephole_keeps_variable_store_then_load() {
+        let instrs = vec![VMInstr::PushInt(1), store("x"), load("x"), VMInstr::Ret];
+        assert_eq!(peephole(program(instrs.clone())).instrs, instrs);
+    }
+
+    #[test]
+    fn peephole_drops_load_then_store() {
+        let prog = program(vec![load("x"), store("x"), load("x"), VMInstr::Ret]);
+        assert_eq!(peephole(prog).instrs, vec![load("x"), VMInstr::Ret]);
+    }
+
+    #[test]
+    fn peephole_pops_unread_temp() {
+        let prog = program(vec![VMInstr::PushInt(1), store("%1"), VMInstr::PushInt(2), VMInstr::Ret]);
+        assert_eq!(peephole(prog).instrs, vec![VMInstr::PushInt(1), VMInstr::Pop, VMInstr::PushInt(2), VMInstr::Ret]);
+    }
+
+    #[test]
+    fn peephole_leaves_jump_targets_alone_and_remaps_jumps() {
+        let prog = program(vec![
+            VMInstr::PushInt(1),
+            store("%1"),
+            load("%1"),
+            VMInstr::Jump(6),
+            VMInstr::PushInt(2),
+            store("%2"),
+            load("%2"),
+            VMInstr::Ret,
+        ]);
+        assert_eq!(
+            peephole(prog).instrs,
+            vec![VMInstr::PushInt(1), VMInstr::Jump(4), VMInstr::PushInt(2), store("%2"), load("%2"), VMInstr::Ret]
+        );
+    }
+
+    #[test]
+    fn callee_cannot_read_callers_locals() {
+        // the entry stub calls main, which stores x and calls f, which loads x
+        let prog = VMProgram {
+            instrs: vec![
+                VMInstr::Call(2, 0),
+                VMInstr::Ret,
+                VMInstr::PushInt(1),
+                store("x"),
+                VMInstr::Call(6, 0),
+                VMInstr::Ret,
+                load("x"),
+                VMInstr::Ret,
+            ],
+            functions: Vec::new(),
+        };
+        assert_eq!(VM::new().run_to_end(&prog), Err(VMError::UndefinedVariable("x".to_string())));
+    }
+
+    #[test]
+    fn breakpoint_pauses_distinctly_and_resume_finishes() {
+        let prog = program(vec![VMInstr::PushInt(1), VMInstr::PushInt(2), VMInstr::Add, VMInstr::Ret]);
+        let mut vm = VM::new();
+        vm.set_breakpoint(2);
+        assert_eq!(vm.run(&prog), Ok(StepResult::Paused));
+        assert!(vm.is_paused());
+        assert_eq!(vm.ip, 2);
+        assert_eq!(vm.stack(), &[VMValue::Int(1), VMValue::Int(2)]);
+        assert_eq!(vm.resume(&prog), Ok(StepResult::Halted(Some(VMValue::Int(3)))));
+    }
+
+    #[test]
+    fn step_runs_one_instruction() {
+        let prog = program(vec![VMInstr::PushInt(7), VMInstr::Ret]);
+        let mut vm = VM::new();
+        assert_eq!(vm.step(&prog), Ok(StepResult::Running));
+        assert_eq!(vm.stack(), &[VMValue::Int(7)]);
+        assert_eq!(vm.step(&prog), Ok(StepResult::Halted(Some(VMValue::Int(7)))));
+    }
+
+    #[test]
+    fn run_starts_from_a_clean_state() {
+        let prog = program(vec![VMInstr::PushStr("hi".to_string()), VMInstr::Print, VMInstr::Ret]);
+        let mut vm = VM::new();
+        vm.set_breakpoint(1);
+        assert_eq!(vm.run(&prog), Ok(StepResult::Paused));
+        vm.clear_breakpoint(1);
+        assert_eq!(vm.run(&prog), Ok(StepResult::Halted(None)));
+        assert_eq!(vm.run(&prog), Ok(StepResult::Halted(None)));
+        assert_eq!(vm.output(), &["hi".to_string()]);
+        assert!(vm.stack().is_empty());
+    }
+
+    // a whole program compiled the way the CLI does it
+    fn build(src: &str) -> VMProgram {
+        let tokens = crate::lex_layer::tokenize(src).unwrap();
+        crate::build_program(&crate::parse(&tokens).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn main_calls_add() {
+        let prog = build("func add(a: Int, b: Int) -> Int { return a + b; } func main() -> Int { return add(2, 3); }");
+        assert_eq!(VM::new().run_to_end(&prog), Ok(Some(VMValue::Int(5))));
+    }
+
+    #[test]
+    fn ret_goes_back_to_the_caller() {
+        let prog = build("func one() -> Int { return 1; } func main() -> Int { var x = one(); return x + one(); }");
+        assert_eq!(VM::new().run_to_end(&prog), Ok(Some(VMValue::Int(2))));
+    }
+
+    const FACT: &str = "func fact(n: Int) -> Int { if (n < 2) { return 1; } return n * fact(n - 1); }";
+
+    #[test]
+    fn recursion_keeps_each_frames_locals() {
+        let prog = build(&format!("{} func main() -> Int {{ return fact(5); }}", FACT));
+        assert_eq!(VM::new().run_to_end(&prog), Ok(Some(VMValue::Int(120))));
+    }
+
+    #[test]
+    fn deep_recursion_overflows() {
+        let prog = build(&format!("{} func main() -> Int {{ return fact(50); }}", FACT));
+        let mut vm = VM::new();
+        vm.set_max_depth(10);
+        assert_eq!(vm.run_to_end(&prog), Err(VMError::StackOverflow));
+    }
+
+    #[test]
+    fn mod_truncates_toward_zero_and_checks_zero() {
+        let run = |a, b| VM::new().run_to_end(&program(vec![VMInstr::PushInt(a), VMInstr::PushInt(b), VMInstr::Mod, VMInstr::Ret]));
+        assert_eq!(run(7, 3), Ok(Some(VMValue::Int(1))));
+        assert_eq!(run(-7, 3), Ok(Some(VMValue::Int(-1))));
+        assert_eq!(run(7, -3), Ok(Some(VMValue::Int(1))));
+        assert_eq!(run(7, 0), Err(VMError::DivisionByZero));
+    }
+
+    #[test]
+    fn runtime_mod_agrees_with_folding() {
+        let prog = build("func f(a: Int, b: Int) -> Int { return a % b; } func main() -> Int { return f(-7, 3); }");
+        assert_eq!(VM::new().run_to_end(&prog), Ok(Some(VMValue::Int(-1))));
+    }
+
+    #[test]
+    fn bytecode_round_trip_runs_the_same() {
+        let prog = build(&format!("{} func main() -> Int {{ print(\"go\"); return fact(5); }}", FACT));
+        let loaded = VMProgram::deserialize(&prog.serialize()).unwrap();
+        assert_eq!(loaded.instrs, prog.instrs);
+        let (mut before, mut after) = (VM::new(), VM::new());
+        assert_eq!(after.run_to_end(&loaded), before.run_to_end(&prog));
+        assert_eq!(after.output(), before.output());
+    }
+
+    #[test]
+    fn truncated_bytecode_is_rejected() {
+        // ends in PushInt 1; Ret, cutting two bytes splits the 8 byte operand
+        let bytes = build("func main() -> Int { return 1; }").serialize();
+        assert!(VMProgram::deserialize(&bytes[..bytes.len() - 2]).is_err());
+        assert!(VMProgram::deserialize(b"nope").is_err());
+    }
+
+    #[test]
+    fn endless_loop_hits_the_step_limit() {
+        let prog = build("func main() { while (yeah) { } }");
+        let mut vm = VM::new();
+        vm.set_max_steps(1000);
+        assert_eq!(vm.run_to_end(&prog), Err(VMError::StepLimitExceeded(1000)));
+    }
+
+    #[test]
+    fn values_display_plainly() {
+        assert_eq!(VMValue::Int(42).to_string(), "42");
+        assert_eq!(VMValue::Float(5.0).to_string(), "5.0");
+        assert_eq!(VMValue::Bool(true).to_string(), "true");
+        assert_eq!(VMValue::Str("hi".to_string()).to_string(), "hi");
+        assert_eq!(VMValue::Char('z').to_string(), "z");
+        assert_eq!(VMValue::Array(vec![VMValue::Int(1), VMValue::Int(2)]).to_string(), "[1, 2]");
+    }
+
+    #[test]
+    fn disassembly_names_jump_targets() {
+        let prog = build(
+            "func f(n: Int) -> Int { var r = 0; if (n < 2) { r = 1; } else { r = 2; } return r; } \
+             func main() -> Int { return f(1); }",
+        );
+        let expected = "\
+0000  Call -> main (0 args)
+0001  Ret
+f:
+0002  Load n
+0003  PushInt 2
+0004  Lt
+0005  JumpIfFalse -> L0
+0006  PushInt 1
+0007  Store r
+0008  Jump -> L1
+L0:
+0009  PushInt 2
+0010  Store r
+L1:
+0011  Load r
+0012  Ret
+main:
+0013  PushInt 1
+0014  Call -> f (1 args)
+0015  Ret
+";
+        assert_eq!(disassemble(&prog), expected);
+    }
+
+    #[test]
+    fn expression_statements_in_a_loop_keep_the_stack_bounded() {
+        let prog = build("func f() -> Int { return 1; } func main() { var i = 0; while (i < 50) { f(); i = i + 1; } }");
+        let mut vm = VM::new();
+        let mut deepest = 0;
+        while vm.step(&prog).unwrap() == StepResult::Running {
+            deepest = deepest.max(vm.stack().len());
+        }
+        assert!(deepest <= 3, "stack grew to {}", deepest);
+    }
 }